@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Duration, Utc};
+use clap::Parser;
+use discord_bot::{
+    error::{exit_code, Error, Result},
+    message_data::{ChannelData, JsonData, MessageData},
+    stats::{favorite_emoji, is_counted, ChannelCounter, Stats},
+    storage::Storage,
+    utils::{env_var, guild_id_from_env, load_env},
+};
+use itertools::Itertools;
+use serenity::all::{
+    ChannelId, CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateEmbed, CreateEmbedFooter, EditInteractionResponse, EventHandler, GatewayIntents, GuildId,
+    Interaction, Ready, ResolvedValue, UserId,
+};
+use serenity::{async_trait, Client};
+
+/// Answers `/stats` with the counters calc reports, for a single user.
+///
+/// Every option can also be set in `.env` with the variable named after it.
+/// They share calc's variables, so both count the same messages.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Channels left out of the counts, with their threads.
+    #[arg(long, env = "CALC_EXCLUDE_CHANNELS", value_delimiter = ',')]
+    exclude_channels: Vec<ChannelId>,
+    /// Count messages and reactions of bots too.
+    #[arg(long, env = "CALC_INCLUDE_BOTS")]
+    include_bots: bool,
+}
+
+struct Bot {
+    guild_id: GuildId,
+    archive: Arc<Archive>,
+}
+
+/// Reads the guild archive for `/stats`, keeping a JSON archive loaded until
+/// the file changes.
+struct Archive {
+    guild_id: GuildId,
+    exclude_channels: Vec<ChannelId>,
+    include_bots: bool,
+    loaded: Mutex<Option<(SystemTime, Arc<JsonData>)>>,
+}
+
+/// Time range selectable with the `period` option.
+#[derive(Debug, Clone, Copy)]
+enum Period {
+    Week,
+    Month,
+    Year,
+    All,
+}
+
+impl Period {
+    fn from_option(value: &str) -> Self {
+        match value {
+            "week" => Self::Week,
+            "month" => Self::Month,
+            "year" => Self::Year,
+            _ => Self::All,
+        }
+    }
+
+    fn since(self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Week => Some(now - Duration::days(7)),
+            Self::Month => Some(now - Duration::days(30)),
+            Self::Year => Some(now - Duration::days(365)),
+            Self::All => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Week => "last 7 days",
+            Self::Month => "last 30 days",
+            Self::Year => "last 365 days",
+            Self::All => "all time",
+        }
+    }
+}
+
+struct StatsQuery {
+    user_id: UserId,
+    channel_id: Option<ChannelId>,
+    period: Period,
+}
+
+fn stats_command() -> CreateCommand {
+    CreateCommand::new("stats")
+        .description("Show message, mention and reaction counts of a member")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "Member to look up, yourself by default",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Channel,
+            "channel",
+            "Only count this channel and its threads",
+        ))
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "period", "Time range to count")
+                .add_string_choice(Period::Week.label(), "week")
+                .add_string_choice(Period::Month.label(), "month")
+                .add_string_choice(Period::Year.label(), "year")
+                .add_string_choice(Period::All.label(), "all"),
+        )
+}
+
+fn parse_query(command: &CommandInteraction) -> StatsQuery {
+    let mut query = StatsQuery {
+        user_id: command.user.id,
+        channel_id: None,
+        period: Period::All,
+    };
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("user", ResolvedValue::User(user, _)) => query.user_id = user.id,
+            ("channel", ResolvedValue::Channel(channel)) => query.channel_id = Some(channel.id),
+            ("period", ResolvedValue::String(period)) => query.period = Period::from_option(period),
+            _ => {}
+        }
+    }
+    query
+}
+
+impl Archive {
+    /// Counts the archived messages matching the channel and period of `query`.
+    fn collect_stats(&self, query: &StatsQuery) -> Result<(Arc<JsonData>, Stats)> {
+        let since = query.period.since(Utc::now());
+        let mut stats = Stats::default();
+        let mut count = |data: &JsonData, message: &MessageData| {
+            if let Some(channel_id) = query.channel_id {
+                let parent_id = data
+                    .channels
+                    .get(&message.channel_id)
+                    .and_then(|channel| channel.parent_id);
+                if message.channel_id != channel_id && parent_id != Some(channel_id) {
+                    return;
+                }
+            }
+            if since.is_some_and(|since| message.send_time < since) {
+                return;
+            }
+            if is_counted(data, message, &self.exclude_channels, self.include_bots) {
+                stats.add(message, &data.members, self.include_bots);
+            }
+        };
+        let data = match Storage::from_env(self.guild_id)? {
+            Storage::Json(path) => {
+                let data = self.load_json(&path)?;
+                for message in data.messages.values().flatten() {
+                    count(&data, message);
+                }
+                data
+            }
+            storage => Arc::new(storage.for_each_message(|data, message| count(data, &message))?),
+        };
+        Ok((data, stats))
+    }
+
+    /// Returns the JSON archive at `path`, loading it again only when it was
+    /// written since the last call.
+    fn load_json(&self, path: &Path) -> Result<Arc<JsonData>> {
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(Error::file(path))?;
+        let mut loaded = self.loaded.lock().unwrap();
+        if let Some((loaded_modified, data)) = loaded.as_ref() {
+            if *loaded_modified == modified {
+                return Ok(data.clone());
+            }
+        }
+        let data = Arc::new(JsonData::load(path)?);
+        *loaded = Some((modified, data.clone()));
+        Ok(data)
+    }
+}
+
+fn rank(counter: &HashMap<UserId, usize>, user_id: UserId) -> Option<usize> {
+    let count = counter.get(&user_id)?;
+    Some(counter.values().filter(|other| *other > count).count() + 1)
+}
+
+fn count_field(counter: &HashMap<UserId, usize>, user_id: UserId) -> String {
+    let count = counter.get(&user_id).copied().unwrap_or_default();
+    match rank(counter, user_id) {
+        Some(rank) => format!("{} (#{})", count, rank),
+        None => count.to_string(),
+    }
+}
+
+fn top_channels(
+    counter: Option<&ChannelCounter>,
+    channels: &HashMap<ChannelId, ChannelData>,
+) -> String {
+    let counter = match counter {
+        Some(counter) if !counter.is_empty() => counter,
+        _ => return "-".to_string(),
+    };
+    counter
+        .iter()
+        .sorted_by(|a, b| a.1.cmp(b.1).reverse())
+        .take(3)
+        .map(|(channel_id, count)| {
+            let name = channels.get(channel_id).cloned().unwrap_or_default().name;
+            format!("#{}: {}", name, count)
+        })
+        .join("\n")
+}
+
+fn stats_embed(query: &StatsQuery, data: &JsonData, stats: &Stats) -> CreateEmbed {
+    let user_id = query.user_id;
+    let user = data.members.get(&user_id).cloned().unwrap_or_default();
+    let scope = match query.channel_id {
+        Some(channel_id) => {
+            let name = data
+                .channels
+                .get(&channel_id)
+                .cloned()
+                .unwrap_or_default()
+                .name;
+            format!("#{}, {}", name, query.period.label())
+        }
+        None => query.period.label().to_string(),
+    };
+    let favorite = stats
+        .user_emoji_sum
+        .get(&user_id)
+        .and_then(favorite_emoji)
+        .map(|(emoji, count)| format!("{} ({})", emoji.display_name(&data.emojis), count))
+        .unwrap_or_else(|| "-".to_string());
+
+    let mut embed = CreateEmbed::new()
+        .title(format!("Stats of {}", user))
+        .description(scope)
+        .field(
+            "Messages",
+            count_field(&stats.user_message_sum, user_id),
+            true,
+        )
+        .field(
+            "Mentioned",
+            count_field(&stats.user_mention_sum, user_id),
+            true,
+        )
+        .field(
+            "Reactions received",
+            count_field(&stats.reaction_sum, user_id),
+            true,
+        )
+        .field(
+            "Reactions given",
+            count_field(&stats.reaction_given_sum, user_id),
+            true,
+        )
+        .field("Favorite reaction", favorite, true)
+        .footer(CreateEmbedFooter::new(format!(
+            "{} messages in range",
+            stats.message_sum
+        )));
+    if query.channel_id.is_none() {
+        embed = embed.field(
+            "Top channels",
+            top_channels(
+                stats.user_message_sum_per_channels.get(&user_id),
+                &data.channels,
+            ),
+            false,
+        );
+    }
+    if let Some(avatar_url) = &user.avatar_url {
+        embed = embed.thumbnail(avatar_url);
+    }
+    embed
+}
+
+impl Bot {
+    async fn stats(&self, ctx: &Context, command: &CommandInteraction) -> serenity::Result<()> {
+        // reading the archive can take longer than the 3 seconds Discord waits
+        command.defer(&ctx.http).await?;
+        let query = parse_query(command);
+        let archive = self.archive.clone();
+        let collected = tokio::task::spawn_blocking(move || {
+            let result = archive.collect_stats(&query);
+            (query, result)
+        })
+        .await;
+        let response = match collected {
+            Ok((query, Ok((data, stats)))) => {
+                EditInteractionResponse::new().embed(stats_embed(&query, &data, &stats))
+            }
+            Ok((_, Err(why))) => {
+                eprintln!("Failed to collect stats: {}", why);
+                EditInteractionResponse::new().content("Failed to read the archive.")
+            }
+            Err(why) => {
+                eprintln!("Collecting stats panicked: {}", why);
+                EditInteractionResponse::new().content("Failed to read the archive.")
+            }
+        };
+        command.edit_response(&ctx.http, response).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventHandler for Bot {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        println!("Connected as {}", ready.user.name);
+        if let Err(why) = self
+            .guild_id
+            .set_commands(&ctx.http, vec![stats_command()])
+            .await
+        {
+            eprintln!("Failed to register commands: {:?}", why);
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let command = match interaction {
+            Interaction::Command(command) => command,
+            _ => return,
+        };
+        if command.guild_id != Some(self.guild_id) || command.data.name != "stats" {
+            return;
+        }
+        if let Err(why) = self.stats(&ctx, &command).await {
+            eprintln!("Failed to answer /stats: {:?}", why);
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    exit_code(run())
+}
+
+#[tokio::main]
+async fn run() -> Result<()> {
+    load_env()?;
+    let args = Args::parse();
+    let token = env_var("TOKEN")?;
+    let guild_id = guild_id_from_env()?;
+    let archive = Archive {
+        guild_id,
+        exclude_channels: args.exclude_channels,
+        include_bots: args.include_bots,
+        loaded: Mutex::new(None),
+    };
+    let bot = Bot {
+        guild_id,
+        archive: Arc::new(archive),
+    };
+    let mut client = Client::builder(&token, GatewayIntents::GUILDS)
+        .event_handler(bot)
+        .await?;
+
+    client.start().await?;
+    Ok(())
+}
@@ -1,345 +1,505 @@
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::hash::Hash;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-use chrono::Datelike;
-use chrono_tz::Asia;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use chrono_tz::Tz;
+use clap::Parser;
 use discord_bot::{
-    message_data::{ChannelData, Emoji, EmojiData, JsonData, MessageData},
-    utils::filename,
+    error::{exit_code, Error, Result},
+    message_data::{ChannelData, Emoji, EmojiData, UserData},
+    period::{Granularity, TimeSeries},
+    report::{ChannelShare, Entry, Format, PeriodReport, Report, Section, Trend, TrendRow},
+    stats::{
+        extract_top, favorite_emoji, is_counted, reply_partners, CounterPerChannel,
+        EmojiCounterPerChannel, EmojiCounterPerUser, Stats, UserCounter, UserCounterPerChannel,
+    },
+    storage::Storage,
+    utils::{guild_id_from_env, load_env},
+    words::WordCounter,
 };
-use dotenvy::dotenv;
 use itertools::Itertools;
-use serenity::all::{ChannelId, EmojiId, GuildId, UserId};
-
-const FIRST_YEAR: usize = 2023;
-const YEARS: usize = 2;
-
-type Counter<K> = HashMap<K, usize>;
-
-type ChannelCounter = Counter<ChannelId>;
-
-type CounterPerChannel<K> = HashMap<K, ChannelCounter>;
-
-type UserCounter = Counter<UserId>;
-type UserCounterPerChannel = CounterPerChannel<UserId>;
-
-type EmojiCounter = Counter<Emoji>;
-type EmojiCounterPerChannel = CounterPerChannel<Emoji>;
-
-type Counters<T> = [T; YEARS];
-
-fn calc_messages(
-    message: &MessageData,
-    user_message_sum: &mut UserCounter,
-    user_message_sum_per_channels: &mut UserCounterPerChannel,
-) {
-    let user_id = message.author_id;
-    let message_count = user_message_sum.entry(user_id).or_default();
-    let message_count_per_channel = user_message_sum_per_channels
-        .entry(user_id)
-        .or_default()
-        .entry(message.channel_id)
-        .or_default();
-
-    *message_count_per_channel += 1;
-    *message_count += 1;
-}
-
-fn calc_mention(
-    message: &MessageData,
-    user_mention_sum: &mut UserCounter,
-    user_mention_sum_per_channels: &mut UserCounterPerChannel,
-) {
-    message.mentions.iter().for_each(|mention| {
-        let mention_count = user_mention_sum.entry(*mention).or_default();
-        let mention_count_per_channel = user_mention_sum_per_channels
-            .entry(*mention)
-            .or_default()
-            .entry(message.channel_id)
-            .or_default();
-
-        *mention_count += 1;
-        *mention_count_per_channel += 1;
-    });
-}
-
-fn calc_emojis(
-    message: &MessageData,
-    emoji_sum: &mut EmojiCounter,
-    emoji_sum_per_channels: &mut EmojiCounterPerChannel,
-    reaction_sum: &mut UserCounter,
-) {
-    let reaction_counter = reaction_sum.entry(message.author_id).or_default();
-    message.reactions.iter().for_each(|(emoji, count)| {
-        let count = *count as usize;
-        let emoji_count = emoji_sum.entry(emoji.clone()).or_default();
-        let emoji_count_per_channel = emoji_sum_per_channels
-            .entry(emoji.clone())
-            .or_default()
-            .entry(message.channel_id)
-            .or_default();
-
-        *emoji_count += count;
-        *emoji_count_per_channel += count;
-        *reaction_counter += count;
-    });
+use serenity::all::{ChannelId, EmojiId, UserId};
+
+/// Reports yearly message, mention and emoji rankings of the guild archive.
+///
+/// Every option can also be set in `.env` with the variable named after it.
+#[derive(Parser, Debug)]
+struct Args {
+    /// First day to count, in the report time zone.
+    #[arg(long, env = "CALC_SINCE", default_value = "2023-01-01")]
+    since: NaiveDate,
+    /// Last day to count, in the report time zone. Defaults to today.
+    #[arg(long, env = "CALC_UNTIL")]
+    until: Option<NaiveDate>,
+    /// Time zone used to split days and years.
+    #[arg(long, env = "CALC_TIMEZONE", default_value = "Asia/Tokyo")]
+    timezone: Tz,
+    /// Channels left out of the counts, with their threads.
+    #[arg(long, env = "CALC_EXCLUDE_CHANNELS", value_delimiter = ',')]
+    exclude_channels: Vec<ChannelId>,
+    /// Users whose message and mention counts are printed for every year.
+    #[arg(long, env = "CALC_HIGHLIGHT_USERS", value_delimiter = ',')]
+    highlight_users: Vec<UserId>,
+    /// Number of entries in each ranking.
+    #[arg(long, env = "CALC_TOP", default_value_t = 10)]
+    top: usize,
+    /// Number of words listed for each user and channel in the word sections,
+    /// which only appear when content is stored (see `CONTENT_CHANNELS`).
+    #[arg(long, env = "CALC_WORDS", default_value_t = 5)]
+    words: usize,
+    /// Custom emojis used fewer times than this over the whole range are flagged as rare.
+    #[arg(long, env = "CALC_RARE_EMOJI_THRESHOLD", default_value_t = 5)]
+    rare_emoji_threshold: usize,
+    /// Count messages and reactions of bots too.
+    #[arg(long, env = "CALC_INCLUDE_BOTS")]
+    include_bots: bool,
+    /// Also report message, mention and reaction totals per day, week, month,
+    /// quarter or year.
+    #[arg(long, env = "CALC_GRANULARITY")]
+    granularity: Option<Granularity>,
+    /// Read this JSON archive, such as a pseudonymized export, instead of the
+    /// guild storage.
+    #[arg(long, env = "CALC_INPUT")]
+    input: Option<PathBuf>,
+    /// Output format: text, json, csv or markdown.
+    #[arg(long, env = "CALC_FORMAT", default_value_t = Format::Text)]
+    format: Format,
+    /// Write the report to this file instead of stdout.
+    #[arg(long, env = "CALC_OUTPUT")]
+    output: Option<PathBuf>,
 }
 
-fn extract_top10<K: Eq + Hash>(counter: Counter<K>) -> Vec<(K, usize)> {
-    counter
-        .into_iter()
-        .sorted_by(|a, b| a.1.cmp(&b.1).reverse())
-        .take(10)
-        .collect()
-}
-
-fn print_data<V, I>(
-    i: Option<usize>,
+fn entry<V, I>(
+    rank: Option<usize>,
     id: &I,
-    count: &usize,
+    count: usize,
     dates: &HashMap<I, V>,
     channels: &HashMap<ChannelId, ChannelData>,
     par_channels: Option<&CounterPerChannel<I>>,
-) where
-    V: Default + Clone + std::fmt::Display,
+) -> Entry
+where
+    V: Default + Clone + Display,
     I: Eq + Hash + Copy,
 {
-    let data = dates.get(id).cloned().unwrap_or(Default::default());
-    if let Some(i) = i {
-        print!("{} ", i + 1);
-    }
-    print!("{}: {}", data, count);
-    if let Some(par_channels) = par_channels {
-        let par_channel = par_channels.get(id).unwrap();
-        print_par_channels(par_channel, channels, *count);
+    let data = dates.get(id).cloned().unwrap_or_default();
+    let entry = Entry::new(rank, data.to_string(), count);
+    match par_channels.and_then(|par_channels| par_channels.get(id)) {
+        Some(par_channel) => {
+            entry.with_channels(ChannelShare::from_counter(par_channel, channels, count))
+        }
+        None => entry,
     }
-    println!();
-}
-
-fn print_par_channels(
-    par_channel: &Counter<ChannelId>,
-    channels: &HashMap<ChannelId, ChannelData>,
-    sum: usize,
-) {
-    par_channel
-        .iter()
-        .sorted_by(|a, b| a.1.cmp(b.1).reverse())
-        .map(|(key, value)| (key, ((*value as f64 / sum as f64) * 100.0) as usize))
-        .sorted_by(|a, b| a.1.cmp(&b.1).reverse())
-        .for_each(|(channel_id, parent)| {
-            let channel = channels.get(channel_id).cloned().unwrap_or_default();
-            print!(" {}: {}%", channel.name, parent);
-        });
 }
 
-fn print_single_data<V, I>(
-    id: &I,
-    counter: &HashMap<I, usize>,
-    dates: &HashMap<I, V>,
-    channels: &HashMap<ChannelId, ChannelData>,
-    par_channels: Option<&CounterPerChannel<I>>,
-) where
-    V: Default + Clone + std::fmt::Display,
-    I: Eq + Hash + Copy,
-{
-    let count = counter.get(id).unwrap();
-    print_data(None, id, count, dates, channels, par_channels);
-}
-
-fn print_dates<V, I>(
+fn ranking<V, I>(
     counter: &[(I, usize)],
     dates: &HashMap<I, V>,
     channels: &HashMap<ChannelId, ChannelData>,
     par_channels: Option<&CounterPerChannel<I>>,
-) where
-    V: Default + Clone + std::fmt::Display,
+) -> Vec<Entry>
+where
+    V: Default + Clone + Display,
     I: Eq + Hash + Copy,
 {
     counter
         .iter()
         .enumerate()
-        .for_each(|(i, (id, count))| print_data(Some(i), id, count, dates, channels, par_channels));
+        .map(|(i, (id, count))| entry(Some(i + 1), id, *count, dates, channels, par_channels))
+        .collect()
 }
 
-fn print_emojis(
+fn emoji_ranking(
     counter: &[(Emoji, usize)],
     emojis: &HashMap<EmojiId, EmojiData>,
     channels: &HashMap<ChannelId, ChannelData>,
     par_channels: Option<&EmojiCounterPerChannel>,
-) {
-    counter.iter().enumerate().for_each(|(i, (emoji, count))| {
-        let output = match emoji {
-            Emoji::Custom(id) => match emojis.get(id).cloned() {
-                Some(emoji_data) => emoji_data.to_string(),
-                None => "Unknown".to_string(),
-            },
-            Emoji::Unicode(name) => name.clone(),
-            _ => "Unknown".to_string(),
-        };
-        print!("{} {}: {}", i + 1, output, count);
-        if let Some(par_channels) = par_channels {
-            let par_channel = par_channels.get(emoji).unwrap();
-            print_par_channels(par_channel, channels, *count);
-        }
-
-        println!()
-    });
+) -> Vec<Entry> {
+    counter
+        .iter()
+        .enumerate()
+        .map(|(i, (emoji, count))| {
+            let entry = Entry::new(Some(i + 1), emoji.display_name(emojis), *count);
+            match par_channels.and_then(|par_channels| par_channels.get(emoji)) {
+                Some(par_channel) => {
+                    entry.with_channels(ChannelShare::from_counter(par_channel, channels, *count))
+                }
+                None => entry,
+            }
+        })
+        .collect()
 }
 
-fn main() {
-    dotenv().unwrap();
-    let guild_id = std::env::var("GUILD_ID")
-        .unwrap()
-        .parse::<GuildId>()
-        .unwrap();
-    let file = std::fs::File::open(filename(guild_id)).unwrap();
-    let data: JsonData = serde_json::from_reader(file).unwrap();
-
-    let members = data.members;
-    let channels = data.channels;
-
-    let not_include_channels: Vec<ChannelId> =
-        vec![869111104243122187.into(), 1095933862657405041.into()];
-
-    let mut user_message_sums: Counters<UserCounter> = Default::default();
-    let mut user_message_sum_par_channels: Counters<UserCounterPerChannel> = Default::default();
+fn reactor_ranking(
+    counter: &[(UserId, usize)],
+    members: &HashMap<UserId, UserData>,
+    emojis: &HashMap<EmojiId, EmojiData>,
+    user_emojis: &EmojiCounterPerUser,
+) -> Vec<Entry> {
+    counter
+        .iter()
+        .enumerate()
+        .map(|(i, (user_id, count))| {
+            let user = members.get(user_id).cloned().unwrap_or_default();
+            let entry = Entry::new(Some(i + 1), user.to_string(), *count);
+            let favorite = user_emojis.get(user_id).and_then(favorite_emoji);
+            match favorite {
+                Some((emoji, emoji_count)) => entry.with_note(format!(
+                    "favorite: {} {}",
+                    emoji.display_name(emojis),
+                    emoji_count
+                )),
+                None => entry,
+            }
+        })
+        .collect()
+}
 
-    let mut user_mention_sums: Counters<UserCounter> = Default::default();
-    let mut user_mention_sum_par_channels: Counters<UserCounterPerChannel> = Default::default();
+fn reply_pair_ranking(
+    counter: &[((UserId, UserId), usize)],
+    members: &HashMap<UserId, UserData>,
+) -> Vec<Entry> {
+    counter
+        .iter()
+        .enumerate()
+        .map(|(i, ((user_id, replied_id), count))| {
+            let user = members.get(user_id).cloned().unwrap_or_default();
+            let replied = members.get(replied_id).cloned().unwrap_or_default();
+            Entry::new(Some(i + 1), format!("{} -> {}", user, replied), *count)
+        })
+        .collect()
+}
 
-    let mut emoji_sums: Counters<EmojiCounter> = Default::default();
-    let mut emoji_sum_per_channels: Counters<EmojiCounterPerChannel> = Default::default();
-    let mut reaction_sums: Counters<UserCounter> = Default::default();
+fn reply_partner_ranking(
+    stats: &Stats,
+    members: &HashMap<UserId, UserData>,
+    top: usize,
+) -> Vec<Entry> {
+    reply_partners(&stats.reply_sum)
+        .into_iter()
+        .sorted_by(|a, b| (a.1).1.cmp(&(b.1).1).reverse())
+        .take(top)
+        .enumerate()
+        .map(|(i, (user_id, (partner_id, count)))| {
+            let user = members.get(&user_id).cloned().unwrap_or_default();
+            let partner = members.get(&partner_id).cloned().unwrap_or_default();
+            Entry::new(Some(i + 1), user.to_string(), count).with_note(format!("to {}", partner))
+        })
+        .collect()
+}
 
-    let mut message_sum: Counters<usize> = Default::default();
-    let mut mention_sum: Counters<usize> = Default::default();
+/// Entries named by `name` with their word total, noting their most used words.
+fn word_ranking<I: Eq + Hash + Copy>(
+    counter: &HashMap<I, WordCounter>,
+    name: impl Fn(&I) -> String,
+    top: usize,
+    words: usize,
+) -> Vec<Entry> {
+    counter
+        .iter()
+        .map(|(id, word_counter)| (id, word_counter.values().sum::<usize>(), word_counter))
+        .sorted_by(|a, b| a.1.cmp(&b.1).reverse())
+        .take(top)
+        .enumerate()
+        .map(|(i, (id, total, word_counter))| {
+            let note = extract_top(word_counter.clone(), words)
+                .into_iter()
+                .map(|(word, count)| format!("{} ({})", word, count))
+                .join(", ");
+            Entry::new(Some(i + 1), name(id), total).with_note(note)
+        })
+        .collect()
+}
 
-    data.messages.iter().for_each(|(channel_id, messages)| {
-        if not_include_channels.contains(channel_id) {
-            return;
+/// Every guild custom emoji with its use over the whole range, least used first.
+fn custom_emoji_usage<'a>(
+    stats: impl Iterator<Item = &'a Stats>,
+    args: &Args,
+    emojis: &HashMap<EmojiId, EmojiData>,
+) -> Section {
+    let mut reactions = HashMap::<Emoji, usize>::new();
+    let mut used = HashMap::<Emoji, usize>::new();
+    let mut last_used = HashMap::<Emoji, DateTime<Utc>>::new();
+    for stats in stats {
+        for (emoji, count) in &stats.emoji_sum {
+            *reactions.entry(emoji.clone()).or_default() += count;
         }
-        messages.iter().for_each(|message| {
-            if let Some(user) = members.get(&message.author_id) {
-                if user.is_bot {
-                    return;
-                }
-            } else {
-                return;
-            }
-            let time = message.send_time.with_timezone(&Asia::Tokyo);
-            if (time.year() as usize) < FIRST_YEAR {
-                return;
+        for (emoji, count) in &stats.used_emoji_sum {
+            *used.entry(emoji.clone()).or_default() += count;
+        }
+        for (emoji, time) in &stats.emoji_last_used {
+            let last = last_used.entry(emoji.clone()).or_insert(*time);
+            *last = (*last).max(*time);
+        }
+    }
+
+    let entries = emojis
+        .keys()
+        .map(|emoji_id| {
+            let emoji = Emoji::from(*emoji_id);
+            let reaction_count = reactions.get(&emoji).copied().unwrap_or_default();
+            let used_count = used.get(&emoji).copied().unwrap_or_default();
+            (emoji, reaction_count, used_count)
+        })
+        .sorted_by(|a, b| {
+            (a.1 + a.2)
+                .cmp(&(b.1 + b.2))
+                .then_with(|| a.0.display_name(emojis).cmp(&b.0.display_name(emojis)))
+        })
+        .map(|(emoji, reaction_count, used_count)| {
+            let total = reaction_count + used_count;
+            let last = match last_used.get(&emoji) {
+                Some(time) => format!(
+                    "last used {}",
+                    time.with_timezone(&args.timezone).date_naive()
+                ),
+                None => "never used".to_string(),
+            };
+            let mut note = format!(
+                "reactions: {} messages: {} {}",
+                reaction_count, used_count, last
+            );
+            if total < args.rare_emoji_threshold {
+                note.push_str(" (rare)");
             }
-            let index = time.year() as usize - FIRST_YEAR;
-            if index >= YEARS {
-                return;
+            Entry::new(None, emoji.display_name(emojis), total).with_note(note)
+        })
+        .collect();
+    Section::new("custom emoji usage", entries)
+}
+
+fn period_report(
+    year: i32,
+    stats: &Stats,
+    args: &Args,
+    members: &HashMap<UserId, UserData>,
+    channels: &HashMap<ChannelId, ChannelData>,
+    emojis: &HashMap<EmojiId, EmojiData>,
+) -> PeriodReport {
+    let user_message_sum = extract_top(stats.user_message_sum.clone(), args.top);
+    let user_mention_sum = extract_top(stats.user_mention_sum.clone(), args.top);
+    let emoji_sum = extract_top(stats.emoji_sum.clone(), args.top);
+    let used_emoji_sum = extract_top(stats.used_emoji_sum.clone(), args.top);
+    let reaction_sum = extract_top(stats.reaction_sum.clone(), args.top);
+    let reaction_given_sum = extract_top(stats.reaction_given_sum.clone(), args.top);
+    let reply_sum = extract_top(stats.reply_sum.clone(), args.top);
+    let emoji_custom_sum = stats
+        .emoji_sum
+        .iter()
+        .sorted_by(|a, b| a.1.cmp(b.1).reverse())
+        .enumerate()
+        .filter(|(_, (emoji, _))| matches!(emoji, Emoji::Custom { .. }))
+        .map(|(i, (emoji, count))| Entry::new(Some(i + 1), emoji.display_name(emojis), *count))
+        .collect();
+
+    let mut sections = vec![
+        Section::new(
+            "message count",
+            ranking(
+                &user_message_sum,
+                members,
+                channels,
+                Some(&stats.user_message_sum_per_channels),
+            ),
+        ),
+        Section::new(
+            "mention count",
+            ranking(
+                &user_mention_sum,
+                members,
+                channels,
+                Some(&stats.user_mention_sum_per_channels),
+            ),
+        ),
+        Section::new(
+            "emoji used as reactions",
+            emoji_ranking(
+                &emoji_sum,
+                emojis,
+                channels,
+                Some(&stats.emoji_sum_per_channels),
+            ),
+        ),
+        Section::new(
+            "emoji used in messages",
+            emoji_ranking(
+                &used_emoji_sum,
+                emojis,
+                channels,
+                Some(&stats.used_emoji_sum_per_channels),
+            ),
+        ),
+        Section::new(
+            "reaction count",
+            ranking(&reaction_sum, members, channels, None),
+        ),
+        Section::new(
+            "reaction given count",
+            reactor_ranking(&reaction_given_sum, members, emojis, &stats.user_emoji_sum),
+        ),
+        Section::new("emoji custom count", emoji_custom_sum),
+        Section::new("reply pair count", reply_pair_ranking(&reply_sum, members)),
+        Section::new(
+            "most replied partner",
+            reply_partner_ranking(stats, members, args.top),
+        ),
+    ];
+
+    if !stats.user_word_sum.is_empty() {
+        let mut word_sum = WordCounter::new();
+        for word_counter in stats.channel_word_sum.values() {
+            for (word, count) in word_counter {
+                *word_sum.entry(word.clone()).or_default() += count;
             }
+        }
+        let word_sum = extract_top(word_sum, args.top)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (word, count))| Entry::new(Some(i + 1), word, count))
+            .collect();
+        sections.push(Section::new("word count", word_sum));
+        sections.push(Section::new(
+            "words per user",
+            word_ranking(
+                &stats.user_word_sum,
+                |user_id| {
+                    members
+                        .get(user_id)
+                        .cloned()
+                        .unwrap_or_default()
+                        .to_string()
+                },
+                args.top,
+                args.words,
+            ),
+        ));
+        sections.push(Section::new(
+            "words per channel",
+            word_ranking(
+                &stats.channel_word_sum,
+                |channel_id| channels.get(channel_id).cloned().unwrap_or_default().name,
+                args.top,
+                args.words,
+            ),
+        ));
+    }
 
-            message_sum[index] += 1;
-            mention_sum[index] += message.mentions.len();
+    if !args.highlight_users.is_empty() {
+        let highlight =
+            |counter: &UserCounter, par_channels: &UserCounterPerChannel| -> Vec<Entry> {
+                args.highlight_users
+                    .iter()
+                    .map(|id| {
+                        let count = counter.get(id).copied().unwrap_or_default();
+                        entry(None, id, count, members, channels, Some(par_channels))
+                    })
+                    .collect()
+            };
+        sections.push(Section::new(
+            "highlighted message count",
+            highlight(
+                &stats.user_message_sum,
+                &stats.user_message_sum_per_channels,
+            ),
+        ));
+        sections.push(Section::new(
+            "highlighted mention count",
+            highlight(
+                &stats.user_mention_sum,
+                &stats.user_mention_sum_per_channels,
+            ),
+        ));
+    }
 
-            calc_messages(
-                message,
-                &mut user_message_sums[index],
-                &mut user_message_sum_par_channels[index],
-            );
+    PeriodReport {
+        year,
+        messages: stats.message_sum,
+        mentions: stats.mention_sum,
+        sections,
+    }
+}
 
-            calc_mention(
-                message,
-                &mut user_mention_sums[index],
-                &mut user_mention_sum_par_channels[index],
-            );
+fn main() -> ExitCode {
+    exit_code(run())
+}
 
-            calc_emojis(
-                message,
-                &mut emoji_sums[index],
-                &mut emoji_sum_per_channels[index],
-                &mut reaction_sums[index],
-            );
-        });
-    });
+fn run() -> Result<()> {
+    load_env()?;
+    let args = Args::parse();
+    let until = args
+        .until
+        .unwrap_or_else(|| Utc::now().with_timezone(&args.timezone).date_naive());
+    let storage = match &args.input {
+        Some(path) => Storage::Json(path.clone()),
+        None => Storage::from_env(guild_id_from_env()?)?,
+    };
+
+    let mut stats = TimeSeries::<Stats>::new(Granularity::Year);
+    let mut trend = args.granularity.map(TimeSeries::<TrendRow>::new);
+
+    let data = storage.for_each_message(|data, message| {
+        if !is_counted(data, &message, &args.exclude_channels, args.include_bots) {
+            return;
+        }
+        let date = message.send_time.with_timezone(&args.timezone).date_naive();
+        if date < args.since || date > until {
+            return;
+        }
 
-    for i in 0..YEARS {
-        let message_sum = message_sum[i];
-        let user_message_sum = extract_top10(user_message_sums[i].clone());
-        let user_message_sum_per_channels = &user_message_sum_par_channels[i];
-        let user_mention_sum = extract_top10(user_mention_sums[i].clone());
-        let user_mention_sum_per_channels = &user_mention_sum_par_channels[i];
-        let emoji_sum = extract_top10(emoji_sums[i].clone());
-        let emoji_sum_per_channels = &emoji_sum_per_channels[i];
-        let reaction_sum = extract_top10(reaction_sums[i].clone());
-        let emoji_custom_usm = emoji_sums[i]
-            .iter()
-            // .filter(|(emoji, _)| matches!(emoji, Emoji::Custom(_)))
-            .map(|(emoji, count)| (emoji.clone(), *count))
-            .sorted_by(|a, b| a.1.cmp(&b.1).reverse())
-            .enumerate()
-            .filter(|(_, (emoji, _))| matches!(emoji, Emoji::Custom(_)))
-            .collect::<Vec<_>>();
+        if let Some(trend) = &mut trend {
+            let row = trend.bucket_mut(date);
+            row.messages += 1;
+            row.mentions += message.mentions.len();
+            row.reactions += message
+                .reactions
+                .values()
+                .chain(message.burst_reactions.values())
+                .sum::<u64>() as usize;
+        }
+        stats
+            .bucket_mut(date)
+            .add(&message, &data.members, args.include_bots);
+    })?;
+    let members = data.members;
+    let channels = data.channels;
 
-        println!();
-        println!("Year: {}", FIRST_YEAR + i);
-        println!("Messages: {}", message_sum);
-        println!("Mentions: {}", mention_sum[i]);
-        println!();
-        println!("message count");
-        print_dates(
-            &user_message_sum,
-            &members,
-            &channels,
-            Some(user_message_sum_per_channels),
-        );
-        println!();
-        println!("mention count");
-        print_dates(
-            &user_mention_sum,
-            &members,
-            &channels,
-            Some(user_mention_sum_per_channels),
-        );
-        println!();
-        println!("emoji count");
-        print_emojis(
-            &emoji_sum,
+    let empty = Stats::default();
+    let report = Report {
+        periods: stats
+            .range(args.since, until)
+            .map(|(period, stats)| {
+                period_report(
+                    period.start.year(),
+                    stats.unwrap_or(&empty),
+                    &args,
+                    &members,
+                    &channels,
+                    &data.emojis,
+                )
+            })
+            .collect(),
+        sections: vec![custom_emoji_usage(
+            stats.iter().map(|(_, stats)| stats),
+            &args,
             &data.emojis,
-            &channels,
-            Some(emoji_sum_per_channels),
-        );
-        println!();
-        println!("reaction count");
-        print_dates(&reaction_sum, &members, &channels, None);
-        println!();
-        println!("emoji custom count");
-        emoji_custom_usm.iter().for_each(|(i, (emoji, count))| {
-            let output = match emoji {
-                Emoji::Custom(id) => match data.emojis.get(id).cloned() {
-                    Some(emoji_data) => emoji_data.to_string(),
-                    None => "Unknown".to_string(),
-                },
-                Emoji::Unicode(name) => name.clone(),
-                _ => "Unknown".to_string(),
-            };
-            println!("{} {}: {}", i + 1, output, count);
-        });
-        println!();
-        let id = 860382628304650240.into();
-
-        print!("Message: ");
-        print_single_data(
-            &id,
-            &user_message_sums[i],
-            &members,
-            &channels,
-            Some(user_message_sum_per_channels),
-        );
-
-        print!("Mention: ");
-        print_single_data(
-            &id,
-            &user_mention_sums[i],
-            &members,
-            &channels,
-            Some(user_mention_sum_per_channels),
-        );
+        )],
+        trend: trend.map(|trend| Trend {
+            granularity: trend.granularity(),
+            rows: trend
+                .range(args.since, until)
+                .map(|(period, row)| TrendRow {
+                    period: period.to_string(),
+                    ..row.cloned().unwrap_or_default()
+                })
+                .collect(),
+        }),
+    };
+    let output = report.render(args.format)?;
+    match &args.output {
+        Some(path) => std::fs::write(path, output).map_err(Error::file(path))?,
+        None => print!("{}", output),
     }
+    Ok(())
 }
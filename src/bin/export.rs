@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use chrono_tz::Tz;
+use clap::Parser;
+use discord_bot::{
+    error::{exit_code, Result},
+    period::Granularity,
+    pseudonym::{pseudonymize, PseudonymOptions},
+    storage::Storage,
+    utils::{guild_id_from_env, load_env},
+};
+
+/// Writes a pseudonymized copy of the guild archive that can be shared.
+///
+/// Users, roles and the guild are replaced with keyed hashes, messages are
+/// renumbered, avatars and content are dropped, and times can be coarsened.
+/// Read the copy with `calc --input`.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Secret the pseudonyms are derived from. Keep it private, anyone holding
+    /// it can check which pseudonym belongs to a known user id.
+    #[arg(long, env = "EXPORT_KEY", hide_env_values = true)]
+    key: String,
+    /// Move send and edit times to the start of their day, week, month, quarter or year.
+    #[arg(long, env = "EXPORT_COARSEN")]
+    coarsen: Option<Granularity>,
+    /// Time zone the coarsened periods start in.
+    #[arg(long, env = "EXPORT_TIMEZONE", default_value = "Asia/Tokyo")]
+    timezone: Tz,
+    /// Keep stored message content.
+    #[arg(long, env = "EXPORT_KEEP_CONTENT")]
+    keep_content: bool,
+    /// Output file. Defaults to `outputs/{guild id}-pseudonymized.json`.
+    #[arg(long, env = "EXPORT_OUTPUT")]
+    output: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    exit_code(run())
+}
+
+fn run() -> Result<()> {
+    load_env()?;
+    let args = Args::parse();
+    let guild_id = guild_id_from_env()?;
+    let data = Storage::from_env(guild_id)?.load()?;
+    let options = PseudonymOptions {
+        key: args.key.into_bytes(),
+        coarsen: args.coarsen,
+        timezone: args.timezone,
+        keep_content: args.keep_content,
+    };
+    let data = pseudonymize(data, &options);
+    let output = args
+        .output
+        .unwrap_or_else(|| format!("outputs/{}-pseudonymized.json", guild_id).into());
+    data.save(&output)?;
+    println!("Wrote {}", output.display());
+    Ok(())
+}
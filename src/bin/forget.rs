@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use discord_bot::{
+    error::{exit_code, Error, Result},
+    forget::{forget_user, ForgetMode},
+    storage::Storage,
+    utils::{guild_id_from_env, load_env},
+};
+use serenity::all::UserId;
+
+/// Deletes a user's data from the guild archive for a deletion request.
+///
+/// Safe to run again: a user that is already forgotten is left as is. Forgotten
+/// users are kept in the archive, so the getter and the recorder forget them
+/// again in whatever they store later.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Users to forget.
+    #[arg(required = true, value_delimiter = ',')]
+    users: Vec<UserId>,
+    /// `remove` deletes their messages, `tombstone` keeps them as sent by
+    /// Discord's "Deleted User" without their content.
+    #[arg(long, default_value_t = ForgetMode::Remove)]
+    mode: ForgetMode,
+    /// Also write the report of what changed as JSON to this file.
+    #[arg(long)]
+    report: Option<PathBuf>,
+    /// Print the report without saving the archive.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+fn main() -> ExitCode {
+    exit_code(run())
+}
+
+fn run() -> Result<()> {
+    load_env()?;
+    let args = Args::parse();
+    let guild_id = guild_id_from_env()?;
+    let mut storage = Storage::from_env(guild_id)?;
+    let mut data = storage.load()?;
+
+    let reports: Vec<_> = args
+        .users
+        .iter()
+        .map(|user_id| forget_user(&mut data, *user_id, args.mode))
+        .collect();
+    for report in &reports {
+        println!(
+            "{} ({}): member removed: {}, overwrites removed: {}, messages removed: {}, \
+             messages tombstoned: {}, mentions: {}, contents: {}, reactions: {}, replies: {}",
+            report.user_id,
+            report.mode,
+            report.member_removed,
+            report.overwrites_removed,
+            report.messages_removed,
+            report.messages_tombstoned,
+            report.mentions_changed,
+            report.contents_changed,
+            report.reactions_changed,
+            report.replies_changed
+        );
+    }
+    if let Some(path) = &args.report {
+        std::fs::write(path, serde_json::to_string_pretty(&reports)?).map_err(Error::file(path))?;
+    }
+
+    if reports.iter().all(|report| report.is_empty()) {
+        println!("Nothing to change");
+    } else if args.dry_run {
+        println!("Dry run, the archive was not changed");
+    } else {
+        storage.save(data)?;
+        println!("Done");
+    }
+    Ok(())
+}
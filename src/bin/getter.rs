@@ -1,164 +1,534 @@
-use std::collections::HashMap;
-use std::env;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::num::NonZeroUsize;
+use std::panic;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use clap::Parser;
+use discord_bot::checkpoint::{ChannelProgress, Checkpoint};
+use discord_bot::error::{exit_code, is_missing_access};
 use discord_bot::message_data::{EmojiData, JsonData, MessageData, UserData};
-use discord_bot::utils::filename;
-use dotenvy::dotenv;
-use serenity::all::{
-    ChannelId, EmojiId, GuildId, MessageId, PermissionOverwriteType, Result, UserId,
+use discord_bot::storage::Storage;
+use discord_bot::utils::{
+    checkpoint_filename, content_channels, env_var, get_reactors, guild_id_from_env,
+    is_skipped_channel, load_env,
 };
-use serenity::{
-    all::{CacheHttp, Context, EventHandler, GatewayIntents, GetMessages, GuildChannel, Message},
-    async_trait, Client,
+use futures::{stream, StreamExt};
+use serenity::all::{
+    ChannelId, ChannelType, EmojiId, GetMessages, GuildChannel, GuildId, Http, Message, Result,
+    UserId,
 };
+use tokio::sync::Mutex;
+use tokio::task;
 
-#[allow(dead_code)]
-async fn get_messages<F: Fn(&Message) -> bool + Copy, F1: Fn(&Message) -> bool + Copy>(
-    cache: impl CacheHttp,
-    channel: GuildChannel,
-    filter: Option<F>,
-    stop: Option<F1>,
-) -> Result<Vec<Message>> {
-    let mut messages: Vec<Message> = Vec::<Message>::new();
-    println!("Channel: {}", channel.name);
-    if !channel.is_text_based() {
-        return Ok(messages);
-    }
-    let mut last_message_id = match channel.last_message_id {
-        Some(x) => x,
-        None => return Ok(messages),
-    };
-    if let Ok(last_message) = channel.message(&cache, last_message_id).await {
-        messages.push(last_message);
-    }
+async fn get_archived_threads(
+    http: &Http,
+    channel_id: ChannelId,
+    private: bool,
+) -> Result<Vec<GuildChannel>> {
+    let mut threads = Vec::<GuildChannel>::new();
+    let mut before = None;
     loop {
-        let get_messages = GetMessages::new().before(last_message_id).limit(100);
-        let new_messages = channel.messages(&cache, get_messages).await?;
-        if new_messages.is_empty() {
-            return Ok(messages);
-        }
-        last_message_id = new_messages.last().unwrap().id;
-        for message in new_messages {
-            if filter.is_some() && !filter.unwrap()(&message) {
+        let data = if private {
+            channel_id
+                .get_archived_private_threads(http, before, Some(100))
+                .await?
+        } else {
+            channel_id
+                .get_archived_public_threads(http, before, Some(100))
+                .await?
+        };
+        threads.extend(data.threads);
+        if !data.has_more {
+            return Ok(threads);
+        }
+        // the pinned serenity takes the cursor as a number, so pass Unix seconds
+        let next = match threads
+            .last()
+            .and_then(|thread| thread.thread_metadata)
+            .and_then(|metadata| metadata.archive_timestamp)
+        {
+            Some(x) => x.unix_timestamp() as u64,
+            None => return Ok(threads),
+        };
+        // stop instead of looping if the cursor is not honoured
+        if before.is_some_and(|before| next >= before) {
+            return Ok(threads);
+        }
+        before = Some(next);
+    }
+}
+
+async fn get_threads(
+    http: &Http,
+    guild_id: GuildId,
+    channels: &HashMap<ChannelId, GuildChannel>,
+) -> Result<HashMap<ChannelId, GuildChannel>> {
+    let mut threads: HashMap<ChannelId, GuildChannel> = guild_id
+        .get_active_threads(http)
+        .await?
+        .threads
+        .into_iter()
+        .map(|t| (t.id, t))
+        .collect();
+    for channel in channels.values() {
+        if !matches!(
+            channel.kind,
+            ChannelType::Text | ChannelType::News | ChannelType::Forum
+        ) {
+            continue;
+        }
+        match get_archived_threads(http, channel.id, false).await {
+            Ok(public_threads) => {
+                threads.extend(public_threads.into_iter().map(|t| (t.id, t)));
+            }
+            Err(why) if is_missing_access(&why) => {
+                eprintln!("Threads of {}: {}", channel.name, why);
                 continue;
             }
-            if stop.is_some() && stop.unwrap()(&message) {
-                return Ok(messages);
+            Err(why) => return Err(why),
+        }
+        if channel.kind != ChannelType::Text {
+            continue;
+        }
+        // listing private threads needs MANAGE_THREADS, so go on without them
+        match get_archived_threads(http, channel.id, true).await {
+            Ok(private_threads) => {
+                threads.extend(private_threads.into_iter().map(|t| (t.id, t)));
             }
-            messages.push(message);
+            Err(why) => eprintln!("Private threads of {}: {:?}", channel.name, why),
         }
     }
+    Ok(threads)
 }
 
-fn is_private_archive_channel(channel: &GuildChannel, guild_id: GuildId) -> bool {
-    for permission_overwrite in channel.permission_overwrites.iter() {
-        match permission_overwrite.kind {
-            PermissionOverwriteType::Role(role_id) => {
-                if role_id.get() != guild_id.get() {
-                    continue;
-                }
+/// Fetches the guild messages into the archive, resuming an interrupted crawl.
+///
+/// Every option can also be set in `.env` with the variable named after it.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Fetch every message again and replace the stored ones.
+    #[arg(long, env = "GETTER_FULL")]
+    full: bool,
+    /// Also fetch who reacted, which takes a request per reaction and another
+    /// for its super reactions.
+    #[arg(long, env = "GETTER_REACTORS")]
+    reactors: bool,
+    /// Channels fetched at the same time. Their requests share the rate limits
+    /// serenity keeps, so a higher value waits on them instead of failing.
+    #[arg(long, env = "GETTER_CONCURRENCY", default_value = "4")]
+    concurrency: NonZeroUsize,
+}
+
+struct Getter {
+    guild_id: GuildId,
+    full: bool,
+    reactors: bool,
+    concurrency: usize,
+    content_channels: HashSet<ChannelId>,
+}
+
+/// Fetched messages are appended next to the checkpoint once this many are
+/// waiting, across all channels, so the checkpoint is not saved for every page.
+const FLUSH_INTERVAL: usize = 1000;
+
+/// Runs disk work on the blocking thread pool, so the channels being fetched
+/// are not held up while it runs.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> discord_bot::error::Result<T> + Send + 'static,
+) -> discord_bot::error::Result<T> {
+    match task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(why) => panic::resume_unwind(why.into_panic()),
+    }
+}
+
+/// Appends fetched messages together with the checkpoint, so the checkpoint
+/// never claims more than was appended.
+struct Progress {
+    checkpoint: Checkpoint,
+    path: String,
+    /// Fetched messages not appended yet, newest first in each channel.
+    pending: HashMap<ChannelId, Vec<MessageData>>,
+    pending_count: usize,
+    /// Channels fetched to the end, completed once their pending messages are appended.
+    finished: Vec<ChannelId>,
+}
+
+impl Progress {
+    /// Appends the pending messages of every channel and saves the checkpoint.
+    async fn flush(&mut self) -> discord_bot::error::Result<()> {
+        // only move the checkpoint once the messages are appended
+        let mut checkpoint = self.checkpoint.clone();
+        let pending = mem::take(&mut self.pending);
+        for (channel_id, messages) in &pending {
+            let channel_progress = checkpoint.channel_mut(*channel_id, None);
+            if let (Some(newest), Some(oldest)) = (messages.first(), messages.last()) {
+                channel_progress
+                    .newest_fetched
+                    .get_or_insert(newest.message_id);
+                channel_progress.oldest_fetched = Some(oldest.message_id);
             }
-            _ => continue,
         }
-        return permission_overwrite.deny.view_channel();
+        self.pending_count = 0;
+        for channel_id in self.finished.drain(..) {
+            checkpoint.channel_mut(channel_id, None).completed = true;
+        }
+        let path = self.path.clone();
+        self.checkpoint = blocking(move || {
+            Checkpoint::append_messages(&path, pending.values().flatten())?;
+            checkpoint.save(&path)?;
+            Ok(checkpoint)
+        })
+        .await?;
+        Ok(())
     }
-    false
 }
 
-#[allow(dead_code)]
-struct Getter {
-    guild_id: GuildId,
+/// Stores the messages appended during the crawl in the archive, once the
+/// crawl stops. A full crawl replaces the archive when it is over, so until
+/// then its messages stay next to the checkpoint.
+fn store(
+    mut storage: Storage,
+    mut metadata: JsonData,
+    mut checkpoint: Checkpoint,
+    path: &str,
+    failed: bool,
+) -> discord_bot::error::Result<()> {
+    if checkpoint.full && failed {
+        return checkpoint.save(path);
+    }
+    // finished channels record where the next crawl stops
+    for (channel_id, channel_progress) in &checkpoint.channels {
+        if !channel_progress.completed {
+            continue;
+        }
+        if let Some(newest) = channel_progress.newest_fetched.or(channel_progress.stop_at) {
+            metadata.fetched_until.insert(*channel_id, newest);
+        }
+    }
+    metadata.messages = Checkpoint::load_messages(path)?;
+    if checkpoint.full {
+        storage.save(metadata)?;
+    } else {
+        storage.merge(metadata)?;
+    }
+    // channels left unfinished resume from the checkpoint next time,
+    // the others from the archive
+    checkpoint
+        .channels
+        .retain(|_, channel_progress| !channel_progress.completed);
+    if checkpoint.channels.is_empty() {
+        return Checkpoint::remove(path);
+    }
+    Checkpoint::remove_messages(path)?;
+    checkpoint.full = false;
+    checkpoint.save(path)
 }
 
-#[async_trait]
-impl EventHandler for Getter {
-    async fn ready(&self, ctx: Context, _ready: serenity::model::gateway::Ready) {
+/// State shared by the channels fetched at the same time.
+struct Crawl {
+    progress: Mutex<Progress>,
+    channel_count: usize,
+    channels_done: AtomicUsize,
+    messages_fetched: AtomicUsize,
+}
+
+impl Crawl {
+    async fn channel_progress(&self, channel_id: ChannelId) -> ChannelProgress {
+        *self
+            .progress
+            .lock()
+            .await
+            .checkpoint
+            .channel_mut(channel_id, None)
+    }
+
+    /// Queues messages of a channel, newest first, flushing once enough are waiting.
+    async fn add(
+        &self,
+        channel_id: ChannelId,
+        messages: Vec<MessageData>,
+    ) -> discord_bot::error::Result<()> {
+        let mut progress = self.progress.lock().await;
+        progress.pending_count += messages.len();
+        progress
+            .pending
+            .entry(channel_id)
+            .or_default()
+            .extend(messages);
+        if progress.pending_count >= FLUSH_INTERVAL {
+            progress.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(&self, channel_id: ChannelId) {
+        self.progress.lock().await.finished.push(channel_id);
+    }
+}
+
+impl Getter {
+    /// Adds a page of messages, newest first, returning whether the messages
+    /// fetched by the previous crawl were reached.
+    async fn add_page(
+        &self,
+        http: &Http,
+        crawl: &Crawl,
+        channel_id: ChannelId,
+        page: Vec<Message>,
+        keep_content: bool,
+    ) -> discord_bot::error::Result<bool> {
+        let stop_at = crawl.channel_progress(channel_id).await.stop_at;
+        let mut reached = false;
+        let mut message_dates = Vec::<MessageData>::with_capacity(page.len());
+        for message in page {
+            if stop_at.is_some_and(|stop_at| message.id <= stop_at) {
+                reached = true;
+                break;
+            }
+            let (reactors, burst_reactors) = if self.reactors {
+                get_reactors(http, &message).await
+            } else {
+                (HashMap::new(), HashMap::new())
+            };
+            let content = keep_content.then(|| message.content.clone());
+            let mut message_data: MessageData = message.into();
+            message_data.reactors = reactors;
+            message_data.burst_reactors = burst_reactors;
+            message_data.content = content;
+            message_dates.push(message_data);
+        }
+        crawl
+            .messages_fetched
+            .fetch_add(message_dates.len(), Ordering::Relaxed);
+        crawl.add(channel_id, message_dates).await?;
+        Ok(reached)
+    }
+
+    /// Fetches a channel from its newest message, or from where an interrupted
+    /// crawl stopped, down to the messages fetched by the previous crawl.
+    async fn fetch_channel(
+        &self,
+        http: &Http,
+        crawl: &Crawl,
+        channel: &GuildChannel,
+        keep_content: bool,
+    ) -> discord_bot::error::Result<()> {
+        let mut before = crawl.channel_progress(channel.id).await.oldest_fetched;
+        if before.is_none() && channel.is_text_based() {
+            if let Some(last_message_id) = channel.last_message_id {
+                // `before` leaves the message itself out, so fetch the newest one apart
+                let page = channel.message(http, last_message_id).await.ok();
+                let page = page.into_iter().collect();
+                if !self
+                    .add_page(http, crawl, channel.id, page, keep_content)
+                    .await?
+                {
+                    before = Some(last_message_id);
+                }
+            }
+        }
+        while let Some(last_message_id) = before {
+            let get_messages = GetMessages::new().before(last_message_id).limit(100);
+            let page = channel.messages(http, get_messages).await?;
+            before = page.last().map(|message| message.id);
+            if self
+                .add_page(http, crawl, channel.id, page, keep_content)
+                .await?
+            {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches a channel. What was fetched is stored even when it fails midway.
+    async fn get_channel(
+        &self,
+        http: &Http,
+        crawl: &Crawl,
+        channel: &GuildChannel,
+        keep_content: bool,
+    ) -> discord_bot::error::Result<()> {
+        self.fetch_channel(http, crawl, channel, keep_content)
+            .await?;
+        crawl.finish(channel.id).await;
+        println!(
+            "[{}/{}] {} done, {} messages fetched",
+            crawl.channels_done.fetch_add(1, Ordering::Relaxed) + 1,
+            crawl.channel_count,
+            channel.name,
+            crawl.messages_fetched.load(Ordering::Relaxed)
+        );
+        Ok(())
+    }
+
+    async fn get(&self, http: &Http) -> discord_bot::error::Result<()> {
         let guild_id: GuildId = self.guild_id;
-        // let guild_id = GuildId::new(986597459323150376);
-        //854616415323815936
-        let guild = guild_id.to_partial_guild(&ctx.http).await.unwrap();
+        let guild = guild_id.to_partial_guild(http).await?;
         println!("Guild: {}", guild.name);
 
         let members: HashMap<UserId, UserData> = guild
-            .members(&ctx.http, None, None)
-            .await
-            .unwrap()
+            .members(http, None, None)
+            .await?
             .into_iter()
             .map(|m| (m.user.id, m.into()))
             .collect();
 
         let emojis: HashMap<EmojiId, EmojiData> = guild
-            .emojis(&ctx.http)
-            .await
-            .unwrap()
+            .emojis(http)
+            .await?
             .into_iter()
             .map(|e| (e.id, e.into()))
             .collect();
 
-        let channels = guild.channels(&ctx.http).await.unwrap();
+        let mut channels = guild.channels(http).await?;
+        let threads = get_threads(http, guild_id, &channels).await?;
+        println!("Threads: {}", threads.len());
+        channels.extend(threads);
 
-        let mut messages = HashMap::<ChannelId, Vec<MessageData>>::new();
-        for (_, channel) in channels.iter() {
-            if is_private_archive_channel(channel, guild_id) {
-                continue;
+        let storage = Storage::from_env(guild_id)?;
+        let mut metadata = JsonData::new(
+            guild_id,
+            members,
+            channels
+                .iter()
+                .map(|(id, c)| (*id, c.clone().into()))
+                .collect(),
+            emojis,
+            HashMap::new(),
+        );
+        let path = checkpoint_filename(guild_id);
+        let saved = match Checkpoint::load(&path)? {
+            // an incremental crawl keeps the stored messages, so it cannot turn into a full one
+            Some(checkpoint) if self.full && !checkpoint.full => {
+                println!("Starting a full crawl over the one saved in {}", path);
+                None
             }
-            let message_dates = get_messages(
-                &ctx.http,
-                channel.clone(),
-                None::<fn(&Message) -> bool>,
-                None::<fn(&Message) -> bool>,
-            )
-            .await
-            .unwrap()
-            .into_iter()
-            .map(|m| m.into())
+            saved => saved,
+        };
+        let mut checkpoint = match saved {
+            Some(checkpoint) => {
+                println!("Resuming the crawl saved in {}", path);
+                checkpoint
+            }
+            None => {
+                // messages left by a crawl that is not resumed are not wanted
+                Checkpoint::remove(&path)?;
+                Checkpoint::new(self.full)
+            }
+        };
+        // recorded messages may be newer, so stop where the last crawl stopped
+        // instead. A full crawl fetches everything again.
+        if !checkpoint.full {
+            metadata.fetched_until = storage.fetched_until()?;
+        }
+
+        let pending_channels: Vec<&GuildChannel> = channels
+            .values()
+            .filter(|channel| !is_skipped_channel(channel, &channels, guild_id))
+            .filter(|channel| {
+                let stop_at = metadata.fetched_until.get(&channel.id).copied();
+                !checkpoint.channel_mut(channel.id, stop_at).completed
+            })
             .collect();
-            messages.insert(channel.id, message_dates);
+        // the starting points must be saved before any message is appended
+        checkpoint.save(&path)?;
+        println!("Channels to fetch: {}", pending_channels.len());
+
+        let crawl = Crawl {
+            progress: Mutex::new(Progress {
+                checkpoint,
+                path,
+                pending: HashMap::new(),
+                pending_count: 0,
+                finished: Vec::new(),
+            }),
+            channel_count: pending_channels.len(),
+            channels_done: AtomicUsize::new(0),
+            messages_fetched: AtomicUsize::new(0),
+        };
+        let content_channels = &self.content_channels;
+        let mut results = stream::iter(pending_channels)
+            .map(|channel| {
+                // threads follow the channel they were created in, channels
+                // do not follow their category
+                let keep_content = content_channels.contains(&channel.id)
+                    || channel.thread_metadata.is_some()
+                        && channel
+                            .parent_id
+                            .is_some_and(|parent_id| content_channels.contains(&parent_id));
+                let crawl = &crawl;
+                async move {
+                    let result = self.get_channel(http, crawl, channel, keep_content).await;
+                    (channel, result)
+                }
+            })
+            .buffer_unordered(self.concurrency);
+
+        let mut inaccessible = Vec::<&GuildChannel>::new();
+        let mut failure = None;
+        while let Some((channel, result)) = results.next().await {
+            match result {
+                Ok(()) => {}
+                Err(why) if why.is_missing_access() => {
+                    eprintln!("Skipping {}: {}", channel.name, why);
+                    inaccessible.push(channel);
+                }
+                // channels still being fetched are dropped, what they fetched
+                // is stored below so the next run resumes from there
+                Err(why) => {
+                    failure = Some(why);
+                    break;
+                }
+            }
         }
+        drop(results);
 
-        let channels = channels.into_iter().map(|(id, c)| (id, c.into())).collect();
-        let data = JsonData::new(guild_id, members, channels, emojis, messages);
-        let mut file = std::fs::File::create(filename(guild_id)).unwrap();
-        serde_json::to_writer(&mut file, &data).unwrap();
-        println!("Done");
+        let mut progress = crawl.progress.into_inner();
+        progress.flush().await?;
+        if !inaccessible.is_empty() {
+            println!("Channels skipped for missing access:");
+            for channel in &inaccessible {
+                println!("  {} ({})", channel.name, channel.id);
+                // fetched again from the archive next time, not from this crawl
+                progress.checkpoint.channels.remove(&channel.id);
+            }
+        }
+        let Progress {
+            checkpoint, path, ..
+        } = progress;
+        let failed = failure.is_some();
+        blocking(move || store(storage, metadata, checkpoint, &path, failed)).await?;
+        match failure {
+            Some(why) => Err(why),
+            None => Ok(()),
+        }
     }
 }
 
-#[allow(dead_code)]
-struct Handler2;
-
-#[async_trait]
-impl EventHandler for Handler2 {
-    async fn ready(&self, ctx: Context, _ready: serenity::model::gateway::Ready) {
-        // let guild_id = GuildId::new(986597459323150376);
-        let message_id = MessageId::new(1311603619300114464);
-        let channel_id = ChannelId::new(596760218755399685);
-        let message = channel_id.message(&ctx.http, message_id).await.unwrap();
-        for reaction in message.reactions.iter() {
-            let reaction_type = &reaction.reaction_type;
-            println!("{:?}", reaction.reaction_type);
-            let users = channel_id
-                .reaction_users(&ctx.http, message_id, reaction_type.clone(), None, None)
-                .await
-                .unwrap();
-            println!("{:?}", users);
-        }
-    }
+fn main() -> ExitCode {
+    exit_code(run())
 }
 
 #[tokio::main]
-async fn main() {
-    dotenv().unwrap();
-    let token = env::var("TOKEN").unwrap();
-    let guild_id = env::var("GUILD_ID").unwrap().parse::<GuildId>().unwrap();
-    println!("Token: {token}");
-    let intents = GatewayIntents::all() - GatewayIntents::GUILD_MESSAGE_TYPING;
-    let mut client = Client::builder(&token, intents)
-        .event_handler(Getter { guild_id })
-        .await
-        .expect("Error creating client");
-
-    if let Err(why) = client.start().await {
-        eprintln!("An error occurred: {:?}", why);
-    }
+async fn run() -> discord_bot::error::Result<()> {
+    load_env()?;
+    let token = env_var("TOKEN")?;
+    let guild_id = guild_id_from_env()?;
+    let args = Args::parse();
+    // everything is fetched over REST, so no gateway connection is needed
+    let http = Http::new(&token);
+    let getter = Getter {
+        guild_id,
+        full: args.full,
+        reactors: args.reactors,
+        concurrency: args.concurrency.get(),
+        content_channels: content_channels()?,
+    };
+    getter.get(&http).await?;
+    println!("Done");
+    Ok(())
 }
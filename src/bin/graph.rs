@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use clap::Parser;
+use discord_bot::{
+    error::{exit_code, Error, Result},
+    graph::InteractionGraph,
+    stats::is_counted,
+    storage::Storage,
+    utils::{guild_id_from_env, load_env},
+};
+use serenity::all::ChannelId;
+
+/// Exports who mentions and replies to whom as GraphML and Graphviz DOT.
+///
+/// Every option can also be set in `.env` with the variable named after it.
+#[derive(Parser, Debug)]
+struct Args {
+    /// First day to count, in the given time zone. Defaults to the first message.
+    #[arg(long, env = "GRAPH_SINCE")]
+    since: Option<NaiveDate>,
+    /// Last day to count, in the given time zone. Defaults to the last message.
+    #[arg(long, env = "GRAPH_UNTIL")]
+    until: Option<NaiveDate>,
+    /// Time zone used to split days.
+    #[arg(long, env = "GRAPH_TIMEZONE", default_value = "Asia/Tokyo")]
+    timezone: Tz,
+    /// Channels left out of the graph, with their threads.
+    #[arg(long, env = "GRAPH_EXCLUDE_CHANNELS", value_delimiter = ',')]
+    exclude_channels: Vec<ChannelId>,
+    /// Leave out edges with fewer interactions.
+    #[arg(long, env = "GRAPH_MIN_WEIGHT", default_value_t = 1)]
+    min_weight: usize,
+    /// Keep bots in the graph.
+    #[arg(long, env = "GRAPH_INCLUDE_BOTS")]
+    include_bots: bool,
+    /// Directory the `.graphml` and `.dot` files are written to.
+    #[arg(long, env = "GRAPH_OUTPUT_DIR", default_value = "outputs")]
+    output_dir: PathBuf,
+}
+
+fn main() -> ExitCode {
+    exit_code(run())
+}
+
+fn run() -> Result<()> {
+    load_env()?;
+    let args = Args::parse();
+    let guild_id = guild_id_from_env()?;
+    let storage = Storage::from_env(guild_id)?;
+
+    let mut graph = InteractionGraph::default();
+    let data = storage.for_each_message(|data, message| {
+        if !is_counted(data, &message, &args.exclude_channels, args.include_bots) {
+            return;
+        }
+        let date = message.send_time.with_timezone(&args.timezone).date_naive();
+        if args.since.is_some_and(|since| date < since)
+            || args.until.is_some_and(|until| date > until)
+        {
+            return;
+        }
+        graph.add(&message, &data.members, args.include_bots);
+    })?;
+    let members = data.members;
+    graph.prune(args.min_weight);
+
+    std::fs::create_dir_all(&args.output_dir).map_err(Error::file(&args.output_dir))?;
+    let graphml = args.output_dir.join(format!("{}.graphml", guild_id));
+    let dot = args.output_dir.join(format!("{}.dot", guild_id));
+    std::fs::write(&graphml, graph.to_graphml(&members)).map_err(Error::file(&graphml))?;
+    std::fs::write(&dot, graph.to_dot(&members)).map_err(Error::file(&dot))?;
+    println!(
+        "{} users, {} edges: {}, {}",
+        graph.nodes().len(),
+        graph.edges.len(),
+        graphml.display(),
+        dot.display()
+    );
+    Ok(())
+}
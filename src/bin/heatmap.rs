@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use clap::Parser;
+use discord_bot::{
+    error::{exit_code, Error, Result},
+    heatmap::{Heatmap, HeatmapStats},
+    stats::is_counted,
+    storage::Storage,
+    utils::{guild_id_from_env, load_env},
+};
+use itertools::Itertools;
+use serenity::all::{ChannelId, UserId};
+
+/// Reports message counts by weekday and hour for the guild, users and channels.
+///
+/// Every option can also be set in `.env` with the variable named after it.
+#[derive(Parser, Debug)]
+struct Args {
+    /// First day to count, in the report time zone. Defaults to the first message.
+    #[arg(long, env = "HEATMAP_SINCE")]
+    since: Option<NaiveDate>,
+    /// Last day to count, in the report time zone. Defaults to the last message.
+    #[arg(long, env = "HEATMAP_UNTIL")]
+    until: Option<NaiveDate>,
+    /// Time zone the weekdays and hours are counted in.
+    #[arg(long, env = "HEATMAP_TIMEZONE", default_value = "Asia/Tokyo")]
+    timezone: Tz,
+    /// Channels left out of the counts, with their threads.
+    #[arg(long, env = "HEATMAP_EXCLUDE_CHANNELS", value_delimiter = ',')]
+    exclude_channels: Vec<ChannelId>,
+    /// Users to report. Defaults to the most active ones.
+    #[arg(long, env = "HEATMAP_USERS", value_delimiter = ',')]
+    users: Vec<UserId>,
+    /// Channels to report. Defaults to the most active ones.
+    #[arg(long, env = "HEATMAP_CHANNELS", value_delimiter = ',')]
+    channels: Vec<ChannelId>,
+    /// Number of users and channels reported when none are given.
+    #[arg(long, env = "HEATMAP_TOP", default_value_t = 5)]
+    top: usize,
+    /// Count messages of bots too.
+    #[arg(long, env = "HEATMAP_INCLUDE_BOTS")]
+    include_bots: bool,
+    /// Also write one SVG image per heatmap to this directory.
+    #[arg(long, env = "HEATMAP_SVG_DIR")]
+    svg_dir: Option<PathBuf>,
+}
+
+/// The given keys, or the keys of the `top` busiest heatmaps.
+fn selected<K: Eq + Hash + Copy>(
+    heatmaps: &HashMap<K, Heatmap>,
+    given: &[K],
+    top: usize,
+) -> Vec<K> {
+    if !given.is_empty() {
+        return given.to_vec();
+    }
+    heatmaps
+        .iter()
+        .map(|(key, heatmap)| (*key, heatmap.total()))
+        .sorted_by(|a, b| a.1.cmp(&b.1).reverse())
+        .take(top)
+        .map(|(key, _)| key)
+        .collect()
+}
+
+fn main() -> ExitCode {
+    exit_code(run())
+}
+
+fn run() -> Result<()> {
+    load_env()?;
+    let args = Args::parse();
+    let guild_id = guild_id_from_env()?;
+    let storage = Storage::from_env(guild_id)?;
+
+    let mut stats = HeatmapStats::default();
+    let data = storage.for_each_message(|data, message| {
+        if !is_counted(data, &message, &args.exclude_channels, args.include_bots) {
+            return;
+        }
+        let date = message.send_time.with_timezone(&args.timezone).date_naive();
+        if args.since.is_some_and(|since| date < since)
+            || args.until.is_some_and(|until| date > until)
+        {
+            return;
+        }
+        stats.add(&message, &args.timezone);
+    })?;
+    let members = data.members;
+    let channels = data.channels;
+
+    let empty = Heatmap::default();
+    let mut heatmaps = vec![("guild".to_string(), "Guild".to_string(), &stats.guild)];
+    for user_id in selected(&stats.users, &args.users, args.top) {
+        let user = members.get(&user_id).cloned().unwrap_or_default();
+        let heatmap = stats.users.get(&user_id).unwrap_or(&empty);
+        heatmaps.push((format!("user-{}", user_id), user.to_string(), heatmap));
+    }
+    for channel_id in selected(&stats.channels, &args.channels, args.top) {
+        let channel = channels.get(&channel_id).cloned().unwrap_or_default();
+        let heatmap = stats.channels.get(&channel_id).unwrap_or(&empty);
+        heatmaps.push((
+            format!("channel-{}", channel_id),
+            format!("#{}", channel),
+            heatmap,
+        ));
+    }
+
+    if let Some(svg_dir) = &args.svg_dir {
+        std::fs::create_dir_all(svg_dir).map_err(Error::file(svg_dir))?;
+    }
+    for (file_stem, title, heatmap) in heatmaps {
+        println!("{} ({} messages)", title, heatmap.total());
+        println!("{}", heatmap.to_text());
+        if let Some(svg_dir) = &args.svg_dir {
+            let path = svg_dir.join(format!("{}.svg", file_stem));
+            std::fs::write(&path, heatmap.to_svg(&title)).map_err(Error::file(&path))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,342 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use discord_bot::error::{exit_code, Result};
+use discord_bot::message_data::{Emoji, MessageData};
+use discord_bot::storage::{MessageChange, Storage};
+use discord_bot::utils::{
+    content_channels, env_var, guild_id_from_env, is_skipped_channel, load_env,
+};
+use serenity::all::{
+    ChannelId, Context, EventHandler, GatewayIntents, GuildId, Message, MessageId,
+    MessageUpdateEvent, Reaction, Ready,
+};
+use serenity::{async_trait, Client};
+
+/// How often changes to a JSON archive are written.
+const JSON_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps the archive current by applying gateway events as they arrive.
+struct Recorder {
+    guild_id: GuildId,
+    storage: Mutex<Storage>,
+    /// Changes waiting to be written to a JSON archive, which every write
+    /// rewrites as a whole, so they are written together every
+    /// [`JSON_FLUSH_INTERVAL`]. The other backends are written at once.
+    pending: Option<Mutex<Vec<MessageChange>>>,
+    content_channels: HashSet<ChannelId>,
+    /// Parents of the archived threads, which the cache does not hold, so
+    /// threads follow their channel's content setting.
+    thread_parents: HashMap<ChannelId, ChannelId>,
+}
+
+fn log_error<T>(event: &str, result: Result<T>) {
+    if let Err(why) = result {
+        eprintln!("Failed to record {}: {}", event, why);
+    }
+}
+
+impl Recorder {
+    /// Whether an event of a channel is recorded: the channel must be in the
+    /// recorded guild and not left out by the getter.
+    fn is_recorded(&self, ctx: &Context, guild_id: Option<GuildId>, channel_id: ChannelId) -> bool {
+        guild_id == Some(self.guild_id) && !self.is_skipped(ctx, channel_id)
+    }
+
+    /// Whether a channel or active thread belongs to the recorded guild and is
+    /// recorded, for events that do not say which guild they come from.
+    fn is_recorded_channel(&self, ctx: &Context, channel_id: ChannelId) -> bool {
+        let in_guild = ctx.cache.guild(self.guild_id).is_some_and(|guild| {
+            guild.channels.contains_key(&channel_id)
+                || guild.threads.iter().any(|thread| thread.id == channel_id)
+        });
+        in_guild && !self.is_skipped(ctx, channel_id)
+    }
+
+    /// Whether the getter leaves the channel out. Archived threads are not in
+    /// the cache, so they are checked through their parent.
+    fn is_skipped(&self, ctx: &Context, channel_id: ChannelId) -> bool {
+        let guild = match ctx.cache.guild(self.guild_id) {
+            Some(guild) => guild,
+            None => return false,
+        };
+        let channel = guild
+            .channels
+            .get(&channel_id)
+            .or_else(|| guild.threads.iter().find(|thread| thread.id == channel_id));
+        match channel {
+            Some(channel) => is_skipped_channel(channel, &guild.channels, self.guild_id),
+            None => self
+                .thread_parents
+                .get(&channel_id)
+                .and_then(|parent_id| guild.channels.get(parent_id))
+                .is_some_and(|parent| is_skipped_channel(parent, &guild.channels, self.guild_id)),
+        }
+    }
+
+    /// Whether content is kept in a channel. Threads follow the channel they
+    /// were created in, but channels do not follow their category.
+    fn keeps_content(&self, ctx: &Context, channel_id: ChannelId) -> bool {
+        if self.content_channels.contains(&channel_id) {
+            return true;
+        }
+        let parent_id = ctx
+            .cache
+            .guild(self.guild_id)
+            .and_then(|guild| {
+                guild
+                    .threads
+                    .iter()
+                    .find(|thread| thread.id == channel_id)?
+                    .parent_id
+            })
+            .or_else(|| self.thread_parents.get(&channel_id).copied());
+        parent_id.is_some_and(|parent_id| self.content_channels.contains(&parent_id))
+    }
+
+    fn message_data(&self, ctx: &Context, message: Message) -> MessageData {
+        let keep_content = self.keeps_content(ctx, message.channel_id);
+        let content = keep_content.then(|| message.content.clone());
+        let mut message_data: MessageData = message.into();
+        message_data.content = content;
+        message_data
+    }
+
+    /// Writes a change, or queues it for the next write of a JSON archive.
+    fn record(&self, event: &str, change: MessageChange) {
+        match &self.pending {
+            Some(pending) => pending.lock().unwrap().push(change),
+            None => log_error(event, self.storage.lock().unwrap().apply(vec![change])),
+        }
+    }
+
+    /// Writes the changes queued for a JSON archive.
+    fn flush(&self) {
+        let changes = match &self.pending {
+            Some(pending) => mem::take(&mut *pending.lock().unwrap()),
+            None => return,
+        };
+        if changes.is_empty() {
+            return;
+        }
+        let result = self.storage.lock().unwrap().apply(changes);
+        log_error("queued changes", result);
+    }
+
+    fn update_message(
+        &self,
+        event: &str,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        f: impl FnOnce(&mut MessageData) + Send + 'static,
+    ) {
+        self.record(
+            event,
+            MessageChange::Update {
+                channel_id,
+                message_id,
+                f: Box::new(f),
+            },
+        );
+    }
+}
+
+#[async_trait]
+impl EventHandler for Recorder {
+    async fn ready(&self, _ctx: Context, ready: Ready) {
+        println!("Recording guild {} as {}", self.guild_id, ready.user.name);
+    }
+
+    async fn message(&self, ctx: Context, new_message: Message) {
+        if !self.is_recorded(&ctx, new_message.guild_id, new_message.channel_id) {
+            return;
+        }
+        let message_data = self.message_data(&ctx, new_message);
+        self.record("message", MessageChange::Upsert(message_data));
+    }
+
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        if !self.is_recorded(&ctx, event.guild_id, event.channel_id) {
+            return;
+        }
+        let message = match new {
+            Some(message) => message,
+            None => match event.channel_id.message(&ctx.http, event.id).await {
+                Ok(message) => message,
+                Err(why) => {
+                    eprintln!("Failed to fetch edited message {}: {:?}", event.id, why);
+                    return;
+                }
+            },
+        };
+        // the gateway does not say who reacted, so the stored reactors are kept
+        let message_data = self.message_data(&ctx, message);
+        self.record("message update", MessageChange::Edit(message_data));
+    }
+
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        guild_id: Option<GuildId>,
+    ) {
+        if !self.is_recorded(&ctx, guild_id, channel_id) {
+            return;
+        }
+        self.record(
+            "message delete",
+            MessageChange::Delete {
+                channel_id,
+                message_id: deleted_message_id,
+            },
+        );
+    }
+
+    async fn message_delete_bulk(
+        &self,
+        ctx: Context,
+        channel_id: ChannelId,
+        multiple_deleted_messages_ids: Vec<MessageId>,
+        guild_id: Option<GuildId>,
+    ) {
+        if !self.is_recorded(&ctx, guild_id, channel_id) {
+            return;
+        }
+        for message_id in multiple_deleted_messages_ids {
+            self.record(
+                "message delete",
+                MessageChange::Delete {
+                    channel_id,
+                    message_id,
+                },
+            );
+        }
+    }
+
+    async fn reaction_add(&self, ctx: Context, add_reaction: Reaction) {
+        if !self.is_recorded(&ctx, add_reaction.guild_id, add_reaction.channel_id) {
+            return;
+        }
+        let emoji = Emoji::from_reaction(add_reaction.emoji);
+        let user_id = add_reaction.user_id;
+        let burst = add_reaction.burst;
+        self.update_message(
+            "reaction add",
+            add_reaction.channel_id,
+            add_reaction.message_id,
+            move |message| message.add_reaction(emoji, user_id, burst),
+        );
+    }
+
+    async fn reaction_remove(&self, ctx: Context, removed_reaction: Reaction) {
+        if !self.is_recorded(&ctx, removed_reaction.guild_id, removed_reaction.channel_id) {
+            return;
+        }
+        let emoji = Emoji::from_reaction(removed_reaction.emoji);
+        let user_id = removed_reaction.user_id;
+        let burst = removed_reaction.burst;
+        self.update_message(
+            "reaction remove",
+            removed_reaction.channel_id,
+            removed_reaction.message_id,
+            move |message| message.remove_reaction(&emoji, user_id, burst),
+        );
+    }
+
+    async fn reaction_remove_all(
+        &self,
+        ctx: Context,
+        channel_id: ChannelId,
+        removed_from_message_id: MessageId,
+    ) {
+        if !self.is_recorded_channel(&ctx, channel_id) {
+            return;
+        }
+        self.update_message(
+            "reaction remove all",
+            channel_id,
+            removed_from_message_id,
+            |message| message.clear_reactions(None),
+        );
+    }
+
+    async fn reaction_remove_emoji(&self, ctx: Context, removed_reactions: Reaction) {
+        if !self.is_recorded(
+            &ctx,
+            removed_reactions.guild_id,
+            removed_reactions.channel_id,
+        ) {
+            return;
+        }
+        let emoji = Emoji::from_reaction(removed_reactions.emoji);
+        self.update_message(
+            "reaction remove emoji",
+            removed_reactions.channel_id,
+            removed_reactions.message_id,
+            move |message| message.clear_reactions(Some(&emoji)),
+        );
+    }
+}
+
+fn main() -> ExitCode {
+    exit_code(run())
+}
+
+#[tokio::main]
+async fn run() -> Result<()> {
+    load_env()?;
+    let token = env_var("TOKEN")?;
+    let guild_id = guild_id_from_env()?;
+    let storage = Storage::from_env(guild_id)?;
+    let pending = matches!(storage, Storage::Json(_)).then(|| Mutex::new(Vec::new()));
+    let thread_parents = storage
+        .load_metadata()
+        .map(|data| {
+            data.channels
+                .into_values()
+                .filter(|channel| channel.is_thread())
+                .filter_map(|channel| Some((channel.channel_id, channel.parent_id?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    let recorder = Arc::new(Recorder {
+        guild_id,
+        storage: Mutex::new(storage),
+        pending,
+        content_channels: content_channels()?,
+        thread_parents,
+    });
+    if recorder.pending.is_some() {
+        let recorder = Arc::clone(&recorder);
+        thread::spawn(move || loop {
+            thread::sleep(JSON_FLUSH_INTERVAL);
+            recorder.flush();
+        });
+    }
+    let intents = GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::GUILD_MESSAGE_REACTIONS
+        | GatewayIntents::MESSAGE_CONTENT;
+    let mut client = Client::builder(&token, intents)
+        .event_handler_arc(Arc::clone(&recorder))
+        .await?;
+
+    // write what is queued before stopping on Ctrl-C
+    tokio::select! {
+        result = client.start() => result?,
+        result = tokio::signal::ctrl_c() => result?,
+    }
+    recorder.flush();
+    Ok(())
+}
@@ -1,74 +1,71 @@
-use std::{collections::HashMap, env};
+use std::collections::HashMap;
+use std::process::ExitCode;
 
-use discord_bot::message_data::{ChannelData, EmojiData, JsonData, UserData};
-use discord_bot::utils::filename;
-use dotenvy::dotenv;
-use serenity::{
-    all::{ChannelId, Context, EmojiId, EventHandler, GatewayIntents, GuildId, Ready, UserId},
-    async_trait, Client,
-};
+use discord_bot::error::{exit_code, Result};
+use discord_bot::message_data::{ChannelData, EmojiData, UserData};
+use discord_bot::storage::Storage;
+use discord_bot::utils::{env_var, guild_id_from_env, load_env};
+use serenity::all::{ChannelId, EmojiId, GuildId, Http, UserId};
 
 struct Updater {
     guild_id: GuildId,
 }
 
-#[async_trait]
-impl EventHandler for Updater {
-    async fn ready(&self, ctx: Context, _ready: Ready) {
+impl Updater {
+    async fn update(&self, http: &Http) -> Result<()> {
         let guild_id = self.guild_id;
-        let file = std::fs::File::open(filename(guild_id)).unwrap();
-        let mut data: JsonData = serde_json::from_reader(file).unwrap();
-        let guild = guild_id.to_partial_guild(&ctx.http).await.unwrap();
+        let mut storage = Storage::from_env(guild_id)?;
+        let guild = guild_id.to_partial_guild(http).await?;
 
         println!("Guild: {}", guild.name);
         let members: HashMap<UserId, UserData> = guild
-            .members(&ctx.http, None, None)
-            .await
-            .unwrap()
+            .members(http, None, None)
+            .await?
             .into_iter()
             .map(|m| (m.user.id, m.into()))
             .collect();
 
         let emojis: HashMap<EmojiId, EmojiData> = guild
-            .emojis(&ctx.http)
-            .await
-            .unwrap()
+            .emojis(http)
+            .await?
             .into_iter()
             .map(|e| (e.id, e.into()))
             .collect();
 
-        let channels: HashMap<ChannelId, ChannelData> = guild
-            .channels(&ctx.http)
-            .await
-            .unwrap()
+        let mut channels: HashMap<ChannelId, ChannelData> = guild
+            .channels(http)
+            .await?
             .into_iter()
             .map(|(id, c)| (id, c.into()))
             .collect();
 
-        data.members = members;
-        data.emojis = emojis;
-        data.channels = channels;
+        storage.update_metadata(|data| {
+            // threads are not listed with the guild channels, keep the archived ones
+            for (channel_id, channel) in data.channels.drain() {
+                if channel.is_thread() {
+                    channels.entry(channel_id).or_insert(channel);
+                }
+            }
 
-        let mut file = std::fs::File::create(filename(guild_id)).unwrap();
-        serde_json::to_writer(&mut file, &data).unwrap();
-        println!("Done");
+            data.members = members;
+            data.emojis = emojis;
+            data.channels = channels;
+        })
     }
 }
 
-#[tokio::main]
-async fn main() {
-    dotenv().unwrap();
-    let token = env::var("TOKEN").unwrap();
-    let guild_id = env::var("GUILD_ID").unwrap().parse::<GuildId>().unwrap();
-    let intents = GatewayIntents::all() - GatewayIntents::GUILD_MESSAGE_TYPING;
-    println!("Token: {}", token);
-
-    let mut client = Client::builder(token, intents)
-        .event_handler(Updater { guild_id })
-        .await
-        .expect("Error creating client");
+fn main() -> ExitCode {
+    exit_code(run())
+}
 
-    if let Err(why) = client.start().await {
-        println!("Client error: {:?}", why);
-    }
+#[tokio::main]
+async fn run() -> Result<()> {
+    load_env()?;
+    let token = env_var("TOKEN")?;
+    let guild_id = guild_id_from_env()?;
+    // the metadata is read over REST, so no gateway connection is needed
+    let http = Http::new(&token);
+    Updater { guild_id }.update(&http).await?;
+    println!("Done");
+    Ok(())
 }
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, MessageId};
+
+use crate::error::{Error, Result};
+use crate::message_data::MessageData;
+
+/// Progress of a getter crawl, saved while it runs so an interrupted crawl
+/// resumes where it stopped instead of starting over. The fetched messages
+/// are appended to a file next to it and only stored in the archive when the
+/// crawl stops, so the archive is written once per run instead of per batch.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Checkpoint {
+    /// Whether the crawl replaces the archive, as `--full` does.
+    pub full: bool,
+    pub channels: HashMap<ChannelId, ChannelProgress>,
+}
+
+/// Messages are fetched from the newest down to `stop_at`, so whatever is
+/// older than `oldest_fetched` and newer than `stop_at` is still missing.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct ChannelProgress {
+    /// Where the previous crawl stopped. Fetching stops there.
+    pub stop_at: Option<MessageId>,
+    /// Newest message fetched and stored, where the next crawl stops.
+    pub newest_fetched: Option<MessageId>,
+    /// Oldest message fetched and stored so far.
+    pub oldest_fetched: Option<MessageId>,
+    pub completed: bool,
+}
+
+impl Checkpoint {
+    pub fn new(full: bool) -> Self {
+        Self {
+            full,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Reads the checkpoint of an interrupted crawl, if there is one.
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(why) if why.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(why) => return Err(Error::file(path)(why)),
+        };
+        Ok(Some(serde_json::from_reader(BufReader::new(file))?))
+    }
+
+    /// Writes the checkpoint through a temporary file, so a crash while
+    /// saving keeps the previous one.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        let file = File::create(&temporary).map_err(Error::file(&temporary))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush().map_err(Error::file(&temporary))?;
+        fs::rename(&temporary, path).map_err(Error::file(path))
+    }
+
+    /// Deletes the checkpoint and its messages once the crawl is over.
+    pub fn remove(path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        Self::remove_messages(path)?;
+        remove_file(path)
+    }
+
+    /// Appends fetched messages to the file kept with the checkpoint at `path`.
+    pub fn append_messages<'a>(
+        path: impl AsRef<Path>,
+        messages: impl IntoIterator<Item = &'a MessageData>,
+    ) -> Result<()> {
+        let path = messages_path(path.as_ref());
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(Error::file(&path))?;
+        let mut writer = BufWriter::new(file);
+        for message in messages {
+            serde_json::to_writer(&mut writer, message)?;
+            writer.write_all(b"\n").map_err(Error::file(&path))?;
+        }
+        writer.flush().map_err(Error::file(&path))
+    }
+
+    /// Reads the messages appended so far, by channel.
+    pub fn load_messages(path: impl AsRef<Path>) -> Result<HashMap<ChannelId, Vec<MessageData>>> {
+        let path = messages_path(path.as_ref());
+        let mut messages = HashMap::<ChannelId, Vec<MessageData>>::new();
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(why) if why.kind() == ErrorKind::NotFound => return Ok(messages),
+            Err(why) => return Err(Error::file(&path)(why)),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(Error::file(&path))?;
+            let message: MessageData = match serde_json::from_str(&line) {
+                Ok(message) => message,
+                // a crash while appending can cut the last line short, and its
+                // messages are fetched again as the checkpoint was not saved
+                Err(why) if why.is_eof() => continue,
+                Err(why) => return Err(why.into()),
+            };
+            messages
+                .entry(message.channel_id)
+                .or_default()
+                .push(message);
+        }
+        Ok(messages)
+    }
+
+    /// Deletes the appended messages once they are stored in the archive.
+    pub fn remove_messages(path: impl AsRef<Path>) -> Result<()> {
+        remove_file(&messages_path(path.as_ref()))
+    }
+
+    /// Returns the progress of a channel, starting it at `stop_at` when the
+    /// channel was not crawled yet.
+    pub fn channel_mut(
+        &mut self,
+        channel_id: ChannelId,
+        stop_at: Option<MessageId>,
+    ) -> &mut ChannelProgress {
+        self.channels
+            .entry(channel_id)
+            .or_insert_with(|| ChannelProgress {
+                stop_at,
+                ..Default::default()
+            })
+    }
+}
+
+fn messages_path(path: &Path) -> PathBuf {
+    path.with_extension("ndjson")
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(why) if why.kind() != ErrorKind::NotFound => Err(Error::file(path)(why)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    fn channel() -> ChannelId {
+        ChannelId::new(1)
+    }
+
+    #[test]
+    fn channels_start_at_the_first_stop_at() {
+        let mut checkpoint = Checkpoint::new(false);
+        checkpoint.channel_mut(channel(), Some(MessageId::new(5)));
+        // later lookups must not move the starting point
+        let progress = checkpoint.channel_mut(channel(), None);
+        assert_eq!(progress.stop_at, Some(MessageId::new(5)));
+        assert_eq!(progress.oldest_fetched, None);
+        assert!(!progress.completed);
+    }
+
+    #[test]
+    fn saved_progress_is_resumed() {
+        let path = env::temp_dir().join(format!("checkpoint-{}.json", process::id()));
+        assert!(Checkpoint::load(&path).unwrap().is_none());
+
+        let mut checkpoint = Checkpoint::new(true);
+        let progress = checkpoint.channel_mut(channel(), Some(MessageId::new(5)));
+        progress.oldest_fetched = Some(MessageId::new(9));
+        checkpoint.save(&path).unwrap();
+
+        let mut resumed = Checkpoint::load(&path).unwrap().unwrap();
+        assert!(resumed.full);
+        let progress = resumed.channel_mut(channel(), None);
+        assert_eq!(progress.stop_at, Some(MessageId::new(5)));
+        assert_eq!(progress.oldest_fetched, Some(MessageId::new(9)));
+
+        Checkpoint::remove(&path).unwrap();
+        assert!(Checkpoint::load(&path).unwrap().is_none());
+        // removing a finished crawl twice is fine
+        Checkpoint::remove(&path).unwrap();
+    }
+
+    #[test]
+    fn appended_messages_are_read_by_channel() {
+        let path = env::temp_dir().join(format!("checkpoint-messages-{}.json", process::id()));
+        assert!(Checkpoint::load_messages(&path).unwrap().is_empty());
+
+        let first = [
+            MessageData::for_test(1, 9, 3),
+            MessageData::for_test(2, 8, 3),
+        ];
+        Checkpoint::append_messages(&path, &first).unwrap();
+        Checkpoint::append_messages(&path, &[MessageData::for_test(1, 7, 3)]).unwrap();
+        // a line cut short by a crash is left out
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(messages_path(&path))
+            .unwrap();
+        file.write_all(b"{\"channel_id\":").unwrap();
+
+        let messages = Checkpoint::load_messages(&path).unwrap();
+        let ids = |channel_id: u64| -> Vec<u64> {
+            messages[&ChannelId::new(channel_id)]
+                .iter()
+                .map(|message| message.message_id.get())
+                .collect()
+        };
+        assert_eq!(ids(1), [9, 7]);
+        assert_eq!(ids(2), [8]);
+
+        Checkpoint::remove(&path).unwrap();
+        assert!(Checkpoint::load_messages(&path).unwrap().is_empty());
+    }
+}
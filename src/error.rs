@@ -0,0 +1,89 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use serenity::http::HttpError;
+
+/// Errors of the library and binaries, worded to be printed as they are.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("environment variable {0} is not set")]
+    MissingEnv(&'static str),
+    #[error("environment variable {name} is invalid: {value:?}")]
+    InvalidEnv { name: &'static str, value: String },
+    #[error("failed to read .env: {0}")]
+    Dotenv(#[from] dotenvy::Error),
+    #[error("Discord request failed: {0}")]
+    Discord(Box<serenity::Error>),
+    #[error("{}: {source}", path.display())]
+    File { path: PathBuf, source: io::Error },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("failed to write CSV: {0}")]
+    Csv(#[from] ::csv::Error),
+    #[error(
+        "archive schema version {found} is newer than the supported version {supported}, \
+         update this binary"
+    )]
+    NewerSchema { found: usize, supported: usize },
+    #[error("invalid archive: {0}")]
+    InvalidArchive(&'static str),
+    #[error("unknown {kind}: {value} (expected {expected})")]
+    UnknownValue {
+        kind: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// boxed, as serenity errors are large enough to bloat every `Result`
+impl From<serenity::Error> for Error {
+    fn from(why: serenity::Error) -> Self {
+        Self::Discord(Box::new(why))
+    }
+}
+
+impl Error {
+    /// Wraps an IO error with the path it happened on.
+    pub fn file(path: impl AsRef<Path>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        move |source| Self::File { path, source }
+    }
+
+    /// Whether Discord refused to show a channel to the bot.
+    pub fn is_missing_access(&self) -> bool {
+        match self {
+            Self::Discord(why) => is_missing_access(why),
+            _ => false,
+        }
+    }
+}
+
+/// Prints the error a binary stopped with, if any, and turns the result into its exit code.
+pub fn exit_code(result: Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(why) => {
+            eprintln!("error: {}", why);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Whether the request failed with Missing Access (50001) or Missing
+/// Permissions (50013), which only affects the channel it was made for.
+pub fn is_missing_access(why: &serenity::Error) -> bool {
+    match why {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => {
+            matches!(response.error.code, 50001 | 50013)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{PermissionOverwriteType, UserId};
+
+use crate::error::{Error, Result};
+use crate::message_data::{JsonData, MessageData, UserData};
+
+/// The account Discord shows as "Deleted User".
+pub const DELETED_USER_ID: UserId = UserId::new(456226577798135808);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ForgetMode {
+    /// Deletes the user's messages and every reference to them.
+    Remove,
+    /// Keeps the messages and reactions but attributes them to the deleted user.
+    Tombstone,
+}
+
+/// What [`forget_user`] changed. All zero when the user was already forgotten.
+#[derive(Serialize, Debug, Clone)]
+pub struct ForgetReport {
+    pub user_id: UserId,
+    pub mode: ForgetMode,
+    /// Whether the user was added to the archive's forgotten users.
+    pub newly_forgotten: bool,
+    pub member_removed: bool,
+    pub overwrites_removed: usize,
+    pub messages_removed: usize,
+    pub messages_tombstoned: usize,
+    pub mentions_changed: usize,
+    pub contents_changed: usize,
+    pub reactions_changed: usize,
+    pub replies_changed: usize,
+}
+
+impl ForgetReport {
+    fn new(user_id: UserId, mode: ForgetMode) -> Self {
+        Self {
+            user_id,
+            mode,
+            newly_forgotten: false,
+            member_removed: false,
+            overwrites_removed: 0,
+            messages_removed: 0,
+            messages_tombstoned: 0,
+            mentions_changed: 0,
+            contents_changed: 0,
+            reactions_changed: 0,
+            replies_changed: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.newly_forgotten
+            && !self.member_removed
+            && self.overwrites_removed == 0
+            && self.messages_removed == 0
+            && self.messages_tombstoned == 0
+            && self.mentions_changed == 0
+            && self.contents_changed == 0
+            && self.reactions_changed == 0
+            && self.replies_changed == 0
+    }
+}
+
+/// Removes or tombstones a user across members, channels and messages, and
+/// adds them to the archive's forgotten users so that the getter and the
+/// recorder do not bring them back. Running it again changes nothing.
+pub fn forget_user(data: &mut JsonData, user_id: UserId, mode: ForgetMode) -> ForgetReport {
+    let mut report = ForgetReport::new(user_id, mode);
+    report.newly_forgotten = data.forgotten_users.insert(user_id, mode) != Some(mode);
+    report.member_removed = data.members.remove(&user_id).is_some();
+
+    for channel in data.channels.values_mut() {
+        let length = channel.permission_overwrites.len();
+        channel
+            .permission_overwrites
+            .retain(|overwrite| overwrite.kind != PermissionOverwriteType::Member(user_id));
+        report.overwrites_removed += length - channel.permission_overwrites.len();
+    }
+
+    for messages in data.messages.values_mut() {
+        if mode == ForgetMode::Remove {
+            let length = messages.len();
+            messages.retain(|message| message.author_id != user_id);
+            report.messages_removed += length - messages.len();
+        }
+        for message in messages.iter_mut() {
+            forget_in_message(message, user_id, mode, &mut report);
+        }
+    }
+
+    if mode == ForgetMode::Tombstone {
+        data.members.entry(DELETED_USER_ID).or_insert_with(|| {
+            let name = "Deleted User".to_string();
+            UserData::new(DELETED_USER_ID, name.clone(), name, None, false)
+        });
+    }
+    report
+}
+
+/// Forgets the archive's forgotten users again in data fetched after they were forgotten.
+pub fn forget_users(data: &mut JsonData, forgotten: &HashMap<UserId, ForgetMode>) {
+    for (&user_id, &mode) in forgotten {
+        forget_user(data, user_id, mode);
+    }
+}
+
+/// Forgets the archive's forgotten users in a single recorded message. Returns
+/// false when a removed user wrote it, so it must not be stored.
+pub fn forget_users_in_message(
+    message: &mut MessageData,
+    forgotten: &HashMap<UserId, ForgetMode>,
+) -> bool {
+    for (&user_id, &mode) in forgotten {
+        if mode == ForgetMode::Remove && message.author_id == user_id {
+            return false;
+        }
+        forget_in_message(
+            message,
+            user_id,
+            mode,
+            &mut ForgetReport::new(user_id, mode),
+        );
+    }
+    true
+}
+
+/// Forgets the user in a message, except for removing it when they wrote it.
+fn forget_in_message(
+    message: &mut MessageData,
+    user_id: UserId,
+    mode: ForgetMode,
+    report: &mut ForgetReport,
+) {
+    if message.author_id == user_id {
+        message.author_id = DELETED_USER_ID;
+        message.content = None;
+        report.messages_tombstoned += 1;
+    }
+
+    let length = message.mentions.len();
+    match mode {
+        ForgetMode::Remove => message.mentions.retain(|mention| *mention != user_id),
+        ForgetMode::Tombstone => {
+            for mention in message.mentions.iter_mut().filter(|id| **id == user_id) {
+                *mention = DELETED_USER_ID;
+                report.mentions_changed += 1;
+            }
+        }
+    }
+    report.mentions_changed += length - message.mentions.len();
+
+    if let Some(content) = &mut message.content {
+        let replacement = match mode {
+            ForgetMode::Remove => String::new(),
+            ForgetMode::Tombstone => format!("<@{}>", DELETED_USER_ID),
+        };
+        let mut changed = false;
+        for mention in [format!("<@{}>", user_id), format!("<@!{}>", user_id)] {
+            if content.contains(&mention) {
+                *content = content.replace(&mention, &replacement);
+                changed = true;
+            }
+        }
+        if changed {
+            report.contents_changed += 1;
+        }
+    }
+
+    let reactions = [
+        (&mut message.reactors, &mut message.reactions),
+        (&mut message.burst_reactors, &mut message.burst_reactions),
+    ];
+    for (reactors, counts) in reactions {
+        for (emoji, users) in reactors.iter_mut() {
+            let length = users.len();
+            match mode {
+                ForgetMode::Remove => {
+                    users.retain(|id| *id != user_id);
+                    let removed = (length - users.len()) as u64;
+                    if let Some(count) = counts.get_mut(emoji) {
+                        *count = count.saturating_sub(removed);
+                    }
+                }
+                ForgetMode::Tombstone => {
+                    for id in users.iter_mut().filter(|id| **id == user_id) {
+                        *id = DELETED_USER_ID;
+                        report.reactions_changed += 1;
+                    }
+                }
+            }
+            report.reactions_changed += length - users.len();
+        }
+        reactors.retain(|_, users| !users.is_empty());
+        counts.retain(|_, count| *count > 0);
+    }
+
+    if let Some(reference) = &mut message.reference {
+        if reference.author_id == Some(user_id) {
+            reference.author_id = match mode {
+                ForgetMode::Remove => None,
+                ForgetMode::Tombstone => Some(DELETED_USER_ID),
+            };
+            report.replies_changed += 1;
+        }
+    }
+}
+
+impl FromStr for ForgetMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "remove" => Ok(Self::Remove),
+            "tombstone" => Ok(Self::Tombstone),
+            _ => Err(Error::UnknownValue {
+                kind: "mode",
+                value: s.to_string(),
+                expected: "remove or tombstone",
+            }),
+        }
+    }
+}
+
+impl Display for ForgetMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Remove => "remove",
+            Self::Tombstone => "tombstone",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::{
+        ChannelId, ChannelType, GuildId, MessageId, PermissionOverwrite, PermissionOverwriteType,
+        Permissions,
+    };
+
+    use super::*;
+    use crate::message_data::{ChannelData, Emoji, ReferenceData, ReferenceKind};
+
+    const GUILD: u64 = 111111111111111111;
+    const ALICE: u64 = 222222222222222222;
+    const BOB: u64 = 333333333333333333;
+    const CHANNEL: u64 = 666666666666666666;
+
+    fn alice() -> UserId {
+        UserId::new(ALICE)
+    }
+
+    fn thumbs_up() -> Emoji {
+        Emoji::from("👍".to_string())
+    }
+
+    /// Alice wrote message 1, Bob mentions, replies to and reacted with her in message 2,
+    /// where she also left a super reaction.
+    fn archive() -> JsonData {
+        let members = [ALICE, BOB]
+            .into_iter()
+            .map(|id| {
+                let user_id = UserId::new(id);
+                let user = UserData::new(user_id, "name".into(), "name".into(), None, false);
+                (user_id, user)
+            })
+            .collect();
+        let channel = ChannelData::new(
+            ChannelId::new(CHANNEL),
+            "general".into(),
+            ChannelType::Text,
+            vec![PermissionOverwrite {
+                allow: Permissions::VIEW_CHANNEL,
+                deny: Permissions::empty(),
+                kind: PermissionOverwriteType::Member(alice()),
+            }],
+            None,
+        );
+        let mut from_alice = MessageData::for_test(CHANNEL, 1, ALICE);
+        from_alice.content = Some("hi".into());
+        let mut from_bob = MessageData::for_test(CHANNEL, 2, BOB);
+        from_bob.mentions = vec![alice()];
+        from_bob.content = Some(format!("<@{ALICE}> and <@!{ALICE}>, hi"));
+        from_bob.reactors = HashMap::from([(thumbs_up(), vec![alice(), UserId::new(BOB)])]);
+        from_bob.reactions = HashMap::from([(thumbs_up(), 2)]);
+        from_bob.burst_reactors = HashMap::from([(thumbs_up(), vec![alice()])]);
+        from_bob.burst_reactions = HashMap::from([(thumbs_up(), 1)]);
+        from_bob.reference = Some(ReferenceData {
+            kind: ReferenceKind::Reply,
+            channel_id: ChannelId::new(CHANNEL),
+            message_id: Some(MessageId::new(1)),
+            author_id: Some(alice()),
+        });
+        JsonData::new(
+            GuildId::new(GUILD),
+            members,
+            HashMap::from([(channel.channel_id, channel)]),
+            HashMap::new(),
+            HashMap::from([(ChannelId::new(CHANNEL), vec![from_bob, from_alice])]),
+        )
+    }
+
+    fn messages(data: &JsonData) -> &[MessageData] {
+        &data.messages[&ChannelId::new(CHANNEL)]
+    }
+
+    #[test]
+    fn remove_deletes_the_messages_and_every_reference() {
+        let mut data = archive();
+        let report = forget_user(&mut data, alice(), ForgetMode::Remove);
+        assert!(report.member_removed);
+        assert_eq!(report.overwrites_removed, 1);
+        assert_eq!(report.messages_removed, 1);
+        assert_eq!(report.contents_changed, 1);
+
+        let json = serde_json::to_string(&data.messages).unwrap();
+        assert!(!json.contains(&ALICE.to_string()));
+        assert!(data.channels[&ChannelId::new(CHANNEL)]
+            .permission_overwrites
+            .is_empty());
+        let message = &messages(&data)[0];
+        assert_eq!(message.content.as_deref(), Some(" and , hi"));
+        assert_eq!(message.reactions[&thumbs_up()], 1);
+        assert!(message.burst_reactions.is_empty());
+        assert!(message.burst_reactors.is_empty());
+        assert_eq!(data.forgotten_users[&alice()], ForgetMode::Remove);
+    }
+
+    #[test]
+    fn tombstone_attributes_everything_to_the_deleted_user() {
+        let mut data = archive();
+        let report = forget_user(&mut data, alice(), ForgetMode::Tombstone);
+        assert_eq!(report.messages_tombstoned, 1);
+        assert_eq!(report.mentions_changed, 1);
+        assert_eq!(report.reactions_changed, 2);
+        assert_eq!(report.replies_changed, 1);
+
+        let json = serde_json::to_string(&data.messages).unwrap();
+        assert!(!json.contains(&ALICE.to_string()));
+        let [from_bob, from_alice] = messages(&data) else {
+            panic!("both messages are kept");
+        };
+        assert_eq!(from_alice.author_id, DELETED_USER_ID);
+        assert!(from_alice.content.is_none());
+        let deleted = format!("<@{}>", DELETED_USER_ID);
+        assert_eq!(
+            from_bob.content.as_deref(),
+            Some(format!("{deleted} and {deleted}, hi").as_str())
+        );
+        assert!(data.members.contains_key(&DELETED_USER_ID));
+        assert!(!data.members.contains_key(&alice()));
+    }
+
+    #[test]
+    fn forgetting_again_changes_nothing() {
+        for mode in [ForgetMode::Remove, ForgetMode::Tombstone] {
+            let mut data = archive();
+            forget_user(&mut data, alice(), mode);
+            assert!(forget_user(&mut data, alice(), mode).is_empty());
+        }
+    }
+
+    #[test]
+    fn refetched_messages_are_forgotten_again() {
+        let mut data = archive();
+        forget_user(&mut data, alice(), ForgetMode::Remove);
+        let forgotten = data.forgotten_users.clone();
+
+        let mut refetched = archive();
+        forget_users(&mut refetched, &forgotten);
+        assert_eq!(messages(&refetched).len(), 1);
+        assert!(!refetched.members.contains_key(&alice()));
+
+        let mut recorded = MessageData::for_test(CHANNEL, 3, ALICE);
+        assert!(!forget_users_in_message(&mut recorded, &forgotten));
+        let mut recorded = MessageData::for_test(CHANNEL, 3, BOB);
+        recorded.mentions = vec![alice()];
+        assert!(forget_users_in_message(&mut recorded, &forgotten));
+        assert!(recorded.mentions.is_empty());
+    }
+}
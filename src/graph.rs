@@ -0,0 +1,87 @@
+mod dot;
+mod graphml;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serenity::all::UserId;
+
+use crate::message_data::{MessageData, UserData};
+
+/// Interactions from one user to another.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EdgeWeight {
+    pub mentions: usize,
+    pub replies: usize,
+}
+
+/// Weighted directed graph of who mentions and replies to whom.
+#[derive(Debug, Clone, Default)]
+pub struct InteractionGraph {
+    /// Keyed by (source user, target user). Sorted so exports are reproducible.
+    pub edges: BTreeMap<(UserId, UserId), EdgeWeight>,
+}
+
+impl EdgeWeight {
+    pub fn total(&self) -> usize {
+        self.mentions + self.replies
+    }
+}
+
+impl InteractionGraph {
+    pub fn add(
+        &mut self,
+        message: &MessageData,
+        members: &HashMap<UserId, UserData>,
+        include_bots: bool,
+    ) {
+        let author_id = message.author_id;
+        let is_counted = |user_id: &UserId| {
+            *user_id != author_id
+                && (include_bots || !members.get(user_id).is_some_and(|user| user.is_bot))
+        };
+        for mention in message
+            .mentions
+            .iter()
+            .filter(|user_id| is_counted(user_id))
+        {
+            self.edges
+                .entry((author_id, *mention))
+                .or_default()
+                .mentions += 1;
+        }
+        if let Some(replied_id) = message.replied_user().filter(is_counted) {
+            self.edges
+                .entry((author_id, replied_id))
+                .or_default()
+                .replies += 1;
+        }
+    }
+
+    /// Drops edges with fewer than `min_weight` interactions.
+    pub fn prune(&mut self, min_weight: usize) {
+        self.edges.retain(|_, weight| weight.total() >= min_weight);
+    }
+
+    /// Users that appear on at least one edge.
+    pub fn nodes(&self) -> BTreeSet<UserId> {
+        self.edges
+            .keys()
+            .flat_map(|(source, target)| [*source, *target])
+            .collect()
+    }
+
+    pub fn to_graphml(&self, members: &HashMap<UserId, UserData>) -> String {
+        graphml::render(self, members)
+    }
+
+    pub fn to_dot(&self, members: &HashMap<UserId, UserData>) -> String {
+        dot::render(self, members)
+    }
+}
+
+fn label(user_id: UserId, members: &HashMap<UserId, UserData>) -> String {
+    match members.get(&user_id) {
+        Some(user) => user.display_name.clone(),
+        None => user_id.to_string(),
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use serenity::all::UserId;
+
+use super::{label, InteractionGraph};
+use crate::message_data::UserData;
+
+pub(super) fn render(graph: &InteractionGraph, members: &HashMap<UserId, UserData>) -> String {
+    let mut out = String::from("digraph interactions {\n");
+    for user_id in graph.nodes() {
+        writeln!(
+            out,
+            "  \"{}\" [label=\"{}\"];",
+            user_id,
+            escape(&label(user_id, members))
+        )
+        .unwrap();
+    }
+    for ((source, target), weight) in &graph.edges {
+        writeln!(
+            out,
+            "  \"{}\" -> \"{}\" [weight={}, mentions={}, replies={}, label=\"{}\"];",
+            source,
+            target,
+            weight.total(),
+            weight.mentions,
+            weight.replies,
+            weight.total()
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
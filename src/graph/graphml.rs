@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use serenity::all::UserId;
+
+use super::{label, InteractionGraph};
+use crate::message_data::UserData;
+
+pub(super) fn render(graph: &InteractionGraph, members: &HashMap<UserId, UserData>) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    out.push_str("  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n");
+    out.push_str(
+        "  <key id=\"username\" for=\"node\" attr.name=\"username\" attr.type=\"string\"/>\n",
+    );
+    out.push_str("  <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"int\"/>\n");
+    out.push_str(
+        "  <key id=\"mentions\" for=\"edge\" attr.name=\"mentions\" attr.type=\"int\"/>\n",
+    );
+    out.push_str("  <key id=\"replies\" for=\"edge\" attr.name=\"replies\" attr.type=\"int\"/>\n");
+    out.push_str("  <graph id=\"interactions\" edgedefault=\"directed\">\n");
+    for user_id in graph.nodes() {
+        let username = members
+            .get(&user_id)
+            .map(|user| user.username.as_str())
+            .unwrap_or_default();
+        writeln!(out, "    <node id=\"{}\">", user_id).unwrap();
+        writeln!(
+            out,
+            "      <data key=\"label\">{}</data>",
+            escape(&label(user_id, members))
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "      <data key=\"username\">{}</data>",
+            escape(username)
+        )
+        .unwrap();
+        out.push_str("    </node>\n");
+    }
+    for ((source, target), weight) in &graph.edges {
+        writeln!(
+            out,
+            "    <edge source=\"{}\" target=\"{}\">",
+            source, target
+        )
+        .unwrap();
+        writeln!(out, "      <data key=\"weight\">{}</data>", weight.total()).unwrap();
+        writeln!(
+            out,
+            "      <data key=\"mentions\">{}</data>",
+            weight.mentions
+        )
+        .unwrap();
+        writeln!(out, "      <data key=\"replies\">{}</data>", weight.replies).unwrap();
+        out.push_str("    </edge>\n");
+    }
+    out.push_str("  </graph>\n");
+    out.push_str("</graphml>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // characters XML 1.0 cannot represent at all
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use chrono::{DateTime, Datelike, TimeZone, Timelike};
+use serde::Serialize;
+use serenity::all::{ChannelId, UserId};
+
+use crate::message_data::MessageData;
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Message counts by weekday (Monday first) and hour of day.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Heatmap {
+    pub counts: [[usize; 24]; 7],
+}
+
+/// Heatmaps of the whole guild, of every author and of every channel.
+#[derive(Debug, Clone, Default)]
+pub struct HeatmapStats {
+    pub guild: Heatmap,
+    pub users: HashMap<UserId, Heatmap>,
+    pub channels: HashMap<ChannelId, Heatmap>,
+}
+
+impl Heatmap {
+    pub fn add<Tz: TimeZone>(&mut self, time: &DateTime<Tz>) {
+        let weekday = time.weekday().num_days_from_monday() as usize;
+        self.counts[weekday][time.hour() as usize] += 1;
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().flatten().sum()
+    }
+
+    pub fn max(&self) -> usize {
+        self.counts
+            .iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Renders a grid with weekdays as rows and hours as columns.
+    pub fn to_text(&self) -> String {
+        let width = self.max().to_string().len().max(2);
+        let mut out = " ".repeat(4);
+        for hour in 0..24 {
+            write!(out, " {:>width$}", format!("{:02}", hour), width = width).unwrap();
+        }
+        out.push('\n');
+        for (weekday, row) in WEEKDAYS.iter().zip(&self.counts) {
+            write!(out, "{:<4}", weekday).unwrap();
+            for count in row {
+                write!(out, " {:>width$}", count, width = width).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Renders an SVG image where darker cells mean more messages.
+    pub fn to_svg(&self, title: &str) -> String {
+        const CELL: usize = 24;
+        const LEFT: usize = 48;
+        const TOP: usize = 48;
+        let width = LEFT + CELL * 24 + 8;
+        let height = TOP + CELL * 7 + 8;
+        let max = self.max().max(1) as f64;
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"sans-serif\" font-size=\"11\">",
+            width, height
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "<text x=\"{}\" y=\"18\" font-size=\"14\">{}</text>",
+            LEFT,
+            escape(title)
+        )
+        .unwrap();
+        for hour in 0..24 {
+            writeln!(
+                out,
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                LEFT + CELL * hour + CELL / 2,
+                TOP - 6,
+                hour
+            )
+            .unwrap();
+        }
+        for (y, (weekday, row)) in WEEKDAYS.iter().zip(&self.counts).enumerate() {
+            writeln!(
+                out,
+                "<text x=\"{}\" y=\"{}\">{}</text>",
+                8,
+                TOP + CELL * y + CELL / 2 + 4,
+                weekday
+            )
+            .unwrap();
+            for (hour, count) in row.iter().enumerate() {
+                // interpolate from white to a dark green
+                let ratio = *count as f64 / max;
+                let channel = |dark: f64| (255.0 - (255.0 - dark) * ratio).round() as u8;
+                writeln!(
+                    out,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+                     fill=\"#{:02x}{:02x}{:02x}\" stroke=\"#ddd\">\
+                     <title>{} {:02}:00 {}</title></rect>",
+                    LEFT + CELL * hour,
+                    TOP + CELL * y,
+                    CELL,
+                    CELL,
+                    channel(27.0),
+                    channel(94.0),
+                    channel(32.0),
+                    weekday,
+                    hour,
+                    count
+                )
+                .unwrap();
+            }
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+impl HeatmapStats {
+    pub fn add<Tz: TimeZone>(&mut self, message: &MessageData, timezone: &Tz) {
+        let time = message.send_time.with_timezone(timezone);
+        self.guild.add(&time);
+        self.users.entry(message.author_id).or_default().add(&time);
+        self.channels
+            .entry(message.channel_id)
+            .or_default()
+            .add(&time);
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use chrono_tz::Tz;
+
+    use super::*;
+
+    const ALICE: u64 = 222222222222222222;
+    const CHANNEL: u64 = 666666666666666666;
+
+    #[test]
+    fn messages_are_bucketed_in_the_time_zone() {
+        // Sunday 20:30 in UTC is Monday 05:30 in Tokyo
+        let mut message = MessageData::for_test(CHANNEL, 1, ALICE);
+        message.send_time = Utc.with_ymd_and_hms(2025, 1, 5, 20, 30, 0).unwrap();
+        let mut stats = HeatmapStats::default();
+        stats.add(&message, &Tz::Asia__Tokyo);
+        stats.add(&message, &Utc);
+
+        assert_eq!(stats.guild.counts[0][5], 1);
+        assert_eq!(stats.guild.counts[6][20], 1);
+        assert_eq!(stats.guild.total(), 2);
+        assert_eq!(stats.users[&UserId::new(ALICE)], stats.guild);
+        assert_eq!(stats.channels[&ChannelId::new(CHANNEL)], stats.guild);
+    }
+
+    #[test]
+    fn text_has_a_row_per_weekday_and_a_column_per_hour() {
+        let mut heatmap = Heatmap::default();
+        heatmap.counts[2][13] = 120;
+        let text = heatmap.to_text();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[0].trim_start().starts_with("00  01"));
+        assert!(lines[3].starts_with("Wed"));
+        assert!(lines[3].contains(" 120"));
+        // columns widen to the largest count
+        assert!(lines.iter().all(|line| line.len() == 4 + 24 * 4));
+    }
+
+    #[test]
+    fn svg_has_a_cell_per_hour_shaded_by_count() {
+        let mut heatmap = Heatmap::default();
+        heatmap.counts[0][0] = 4;
+        heatmap.counts[0][1] = 2;
+        let svg = heatmap.to_svg("<general> & more");
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains("&lt;general&gt; &amp; more"));
+        assert_eq!(svg.matches("<rect ").count(), 7 * 24);
+        assert!(svg.contains("fill=\"#1b5e20\""));
+        assert!(svg.contains("fill=\"#8daf90\""));
+        assert_eq!(svg.matches("fill=\"#ffffff\"").count(), 7 * 24 - 2);
+    }
+}
@@ -1,2 +1,13 @@
+pub mod checkpoint;
+pub mod error;
+pub mod forget;
+pub mod graph;
+pub mod heatmap;
 pub mod message_data;
+pub mod period;
+pub mod pseudonym;
+pub mod report;
+pub mod stats;
+pub mod storage;
 pub mod utils;
+pub mod words;
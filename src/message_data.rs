@@ -1,27 +1,54 @@
 mod channels;
 mod emoji;
 mod message;
+pub(crate) mod migration;
 mod user;
 
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
 
 use serde::{Deserialize, Serialize};
-use serenity::all::{ChannelId, EmojiId, GuildId, UserId};
+use serenity::all::{ChannelId, EmojiId, GuildId, MessageId, UserId};
+
+use crate::error::{Error, Result};
+use crate::forget::ForgetMode;
 
 pub use channels::ChannelData;
-pub use emoji::Emoji;
 pub use emoji::EmojiData;
-pub use message::MessageData;
+pub use emoji::{content_emojis, Emoji, UnknownReaction};
+pub use message::{MessageData, ReferenceData, ReferenceKind};
+pub use migration::SCHEMA_VERSION;
 pub use user::UserData;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[non_exhaustive]
 pub struct JsonData {
+    pub version: u32,
     pub guild_id: GuildId,
     pub members: HashMap<UserId, UserData>,
     pub channels: HashMap<ChannelId, ChannelData>,
     pub emojis: HashMap<EmojiId, EmojiData>,
     pub messages: HashMap<ChannelId, Vec<MessageData>>,
+    /// Newest message of each channel the getter fetched. The recorder may have
+    /// stored newer ones, so the getter fetches down to here to fill any gap.
+    pub fetched_until: HashMap<ChannelId, MessageId>,
+    /// Users deleted with the forget binary, forgotten again in whatever is stored later.
+    pub forgotten_users: HashMap<UserId, ForgetMode>,
+}
+
+/// An archive without its messages, which serde skips without building them.
+#[derive(Deserialize)]
+struct Metadata {
+    version: u32,
+    guild_id: GuildId,
+    members: HashMap<UserId, UserData>,
+    channels: HashMap<ChannelId, ChannelData>,
+    emojis: HashMap<EmojiId, EmojiData>,
+    fetched_until: HashMap<ChannelId, MessageId>,
+    forgotten_users: HashMap<UserId, ForgetMode>,
 }
 
 impl JsonData {
@@ -33,11 +60,173 @@ impl JsonData {
         messages: HashMap<ChannelId, Vec<MessageData>>,
     ) -> Self {
         Self {
+            version: SCHEMA_VERSION,
             guild_id,
             members,
             channels,
             emojis,
             messages,
+            fetched_until: HashMap::new(),
+            forgotten_users: HashMap::new(),
+        }
+    }
+
+    /// Reads an archive, upgrading it from older schema versions. An archive
+    /// of the current version is parsed straight into `JsonData`, older ones
+    /// are upgraded as a JSON value first.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(Error::file(path))?;
+        match serde_json::from_reader::<_, Self>(BufReader::new(file)) {
+            Ok(data) if data.version == SCHEMA_VERSION => return Ok(data),
+            // older archives may lack fields, and newer ones fail the upgrade
+            _ => {}
+        }
+        let file = File::open(path).map_err(Error::file(path))?;
+        let value = serde_json::from_reader(BufReader::new(file))?;
+        let value = migration::migrate(value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Reads an archive without its messages. With the current schema version
+    /// the messages are skipped while parsing, older archives are upgraded whole.
+    pub fn load_metadata(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(Error::file(path))?;
+        match serde_json::from_reader::<_, Metadata>(BufReader::new(file)) {
+            Ok(metadata) if metadata.version == SCHEMA_VERSION => Ok(Self {
+                version: metadata.version,
+                guild_id: metadata.guild_id,
+                members: metadata.members,
+                channels: metadata.channels,
+                emojis: metadata.emojis,
+                messages: HashMap::new(),
+                fetched_until: metadata.fetched_until,
+                forgotten_users: metadata.forgotten_users,
+            }),
+            // older archives may lack fields, and newer ones fail the upgrade
+            _ => {
+                let mut data = Self::load(path)?;
+                data.messages.clear();
+                Ok(data)
+            }
+        }
+    }
+
+    /// Writes the archive through a temporary file, so a crash while saving
+    /// keeps the previous one.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        let file = File::create(&temporary).map_err(Error::file(&temporary))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush().map_err(Error::file(&temporary))?;
+        fs::rename(&temporary, path).map_err(Error::file(path))
+    }
+
+    /// Adds messages to the channel, skipping ones that are already stored.
+    /// Messages are kept in newest first order, as the getter fetches them.
+    pub fn merge_messages(&mut self, channel_id: ChannelId, messages: Vec<MessageData>) {
+        let stored = self.messages.entry(channel_id).or_default();
+        let mut known: HashSet<MessageId> = stored.iter().map(|m| m.message_id).collect();
+        for message in messages {
+            if known.insert(message.message_id) {
+                stored.push(message);
+            }
         }
+        stored.sort_by_key(|message| Reverse(message.message_id));
+    }
+
+    /// Returns the stored message with this id, for the recorder to edit.
+    pub fn message_mut(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Option<&mut MessageData> {
+        self.messages
+            .get_mut(&channel_id)?
+            .iter_mut()
+            .find(|message| message.message_id == message_id)
+    }
+
+    /// Stores the message, replacing the stored one with the same id.
+    pub fn upsert_message(&mut self, message: MessageData) {
+        match self.message_mut(message.channel_id, message.message_id) {
+            Some(stored) => *stored = message,
+            None => self.merge_messages(message.channel_id, vec![message]),
+        }
+    }
+
+    pub fn remove_message(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Option<MessageData> {
+        let messages = self.messages.get_mut(&channel_id)?;
+        let index = messages
+            .iter()
+            .position(|message| message.message_id == message_id)?;
+        Some(messages.remove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn metadata_is_read_without_messages() {
+        let path = env::temp_dir().join(format!("archive-{}.json", process::id()));
+        let channel_id = ChannelId::new(2);
+        let mut data = JsonData::new(
+            GuildId::new(1),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::from([(channel_id, vec![MessageData::for_test(2, 3, 4)])]),
+        );
+        data.fetched_until.insert(channel_id, MessageId::new(3));
+        data.save(&path).unwrap();
+
+        let metadata = JsonData::load_metadata(&path).unwrap();
+        assert!(metadata.messages.is_empty());
+        assert_eq!(metadata.fetched_until, data.fetched_until);
+        assert_eq!(
+            JsonData::load(&path).unwrap().messages[&channel_id].len(),
+            1
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn only_older_archives_are_upgraded_on_load() {
+        let path = env::temp_dir().join(format!("old-archive-{}.json", process::id()));
+        let data = JsonData::new(
+            GuildId::new(1),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let mut value = serde_json::to_value(&data).unwrap();
+        let root = value.as_object_mut().unwrap();
+        for key in ["version", "fetched_until", "forgotten_users"] {
+            root.remove(key);
+        }
+        fs::write(&path, value.to_string()).unwrap();
+        assert_eq!(JsonData::load(&path).unwrap().version, SCHEMA_VERSION);
+
+        let mut value = serde_json::to_value(&data).unwrap();
+        value["version"] = (SCHEMA_VERSION + 1).into();
+        fs::write(&path, value.to_string()).unwrap();
+        assert!(matches!(
+            JsonData::load(&path),
+            Err(Error::NewerSchema { .. })
+        ));
+        fs::remove_file(path).unwrap();
     }
 }
@@ -9,6 +9,7 @@ pub struct ChannelData {
     pub name: String,
     pub channel_type: ChannelType,
     pub permission_overwrites: Vec<PermissionOverwrite>,
+    pub parent_id: Option<ChannelId>,
 }
 
 impl Display for ChannelData {
@@ -23,14 +24,23 @@ impl ChannelData {
         name: String,
         channel_type: ChannelType,
         permission_overwrites: Vec<PermissionOverwrite>,
+        parent_id: Option<ChannelId>,
     ) -> Self {
         Self {
             channel_id,
             name,
             channel_type,
             permission_overwrites,
+            parent_id,
         }
     }
+
+    pub fn is_thread(&self) -> bool {
+        matches!(
+            self.channel_type,
+            ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread
+        )
+    }
 }
 
 impl From<GuildChannel> for ChannelData {
@@ -39,6 +49,7 @@ impl From<GuildChannel> for ChannelData {
             channel_id: channel.id,
             channel_type: channel.kind,
             permission_overwrites: channel.permission_overwrites,
+            parent_id: channel.parent_id,
             name: channel.name,
         }
     }
@@ -51,6 +62,7 @@ impl Default for ChannelData {
             name: "Unknown".to_string(),
             channel_type: Default::default(),
             permission_overwrites: Default::default(),
+            parent_id: None,
         }
     }
 }
@@ -1,19 +1,247 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
 
-use serde::{Deserialize, Serialize};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serenity::all::{Emoji as SerenityEmoji, EmojiId, ReactionType};
+use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Serialize, Deserialize, Debug, Hash, Eq, PartialEq, Clone)]
-#[serde(untagged)]
+/// A reaction or in-text emoji. Custom emojis are equal when their ids are,
+/// whether or not the name is known.
+///
+/// Serialized as a string so it can key JSON maps: `<:name:id>` or
+/// `<a:name:id>` for custom emojis, the id alone when the name is unknown,
+/// and the emoji itself for Unicode ones.
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum Emoji {
-    Custom(EmojiId),
+    Custom {
+        id: EmojiId,
+        /// Kept for emojis of other servers, which are missing from the guild emojis.
+        name: Option<String>,
+        animated: bool,
+    },
     Unicode(String),
+    /// A reaction type this build does not recognize, kept as its raw JSON.
+    Unknown(String),
+}
+
+/// Error for a reaction type that is neither a custom nor a Unicode emoji.
+#[derive(Debug, Clone)]
+pub struct UnknownReaction(pub String);
+
+impl Emoji {
+    pub fn custom(id: EmojiId, name: Option<String>, animated: bool) -> Self {
+        Self::Custom { id, name, animated }
+    }
+
+    /// Renders the emoji, looking custom ones up in the guild emojis and
+    /// falling back to the name they were seen with.
+    pub fn display_name(&self, emojis: &HashMap<EmojiId, EmojiData>) -> String {
+        match self {
+            Emoji::Custom { id, name, .. } => match (emojis.get(id), name) {
+                (Some(emoji_data), _) => emoji_data.to_string(),
+                (None, Some(name)) => format!(":{}:", name),
+                (None, None) => "Unknown".to_string(),
+            },
+            Emoji::Unicode(name) => name.clone(),
+            Emoji::Unknown(_) => "Unknown reaction".to_string(),
+        }
+    }
+
+    /// Converts a reaction, logging and keeping unrecognized ones as [`Emoji::Unknown`].
+    pub fn from_reaction(reaction_type: ReactionType) -> Self {
+        match Self::try_from(reaction_type) {
+            Ok(emoji) => emoji,
+            Err(why) => {
+                eprintln!("{}, keeping it as is", why);
+                why.into()
+            }
+        }
+    }
+
+    /// The string the emoji is stored as, which also orders emojis stably.
+    pub fn key(&self) -> String {
+        match self {
+            Emoji::Custom {
+                id,
+                name: Some(name),
+                animated,
+            } => format!("<{}:{}:{}>", if *animated { "a" } else { "" }, name, id),
+            Emoji::Custom { id, name: None, .. } => id.to_string(),
+            Emoji::Unicode(name) => name.clone(),
+            Emoji::Unknown(raw) => format!("{}{}", UNKNOWN_PREFIX, raw),
+        }
+    }
+
+    fn from_key(key: String) -> Self {
+        if let Some(raw) = key.strip_prefix(UNKNOWN_PREFIX) {
+            return Self::Unknown(raw.to_string());
+        }
+        if let Some(captures) = custom_emoji_pattern().captures(&key) {
+            if captures
+                .get(0)
+                .is_some_and(|whole| whole.len() == key.len())
+            {
+                if let Ok(id) = captures[3].parse::<u64>() {
+                    let animated = !captures[1].is_empty();
+                    return Self::custom(EmojiId::new(id), Some(captures[2].to_string()), animated);
+                }
+            }
+        }
+        // archives before names were kept stored custom emojis as their id
+        match key.parse::<u64>() {
+            Ok(id) if id != 0 => EmojiId::new(id).into(),
+            _ => Self::Unicode(key),
+        }
+    }
+}
+
+impl PartialEq for Emoji {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Emoji::Custom { id, .. }, Emoji::Custom { id: other, .. }) => id == other,
+            (Emoji::Unicode(name), Emoji::Unicode(other)) => name == other,
+            (Emoji::Unknown(raw), Emoji::Unknown(other)) => raw == other,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Emoji {}
+
+impl Hash for Emoji {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Emoji::Custom { id, .. } => {
+                state.write_u8(0);
+                id.hash(state);
+            }
+            Emoji::Unicode(name) => {
+                state.write_u8(1);
+                name.hash(state);
+            }
+            Emoji::Unknown(raw) => {
+                state.write_u8(2);
+                raw.hash(state);
+            }
+        }
+    }
+}
+
+impl Serialize for Emoji {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.key())
+    }
+}
+
+impl<'de> Deserialize<'de> for Emoji {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::from_key(String::deserialize(deserializer)?))
+    }
+}
+
+/// Marks serialized unknown reactions, which no emoji starts with.
+const UNKNOWN_PREFIX: &str = "unknown:";
+
+fn custom_emoji_pattern() -> &'static Regex {
+    static CUSTOM_EMOJI: OnceLock<Regex> = OnceLock::new();
+    CUSTOM_EMOJI.get_or_init(|| Regex::new(r"<(a?):(\w+):(\d+)>").unwrap())
+}
+
+/// Counts the custom emojis (`<:name:id>`, `<a:name:id>`) and Unicode emoji
+/// sequences typed in message content.
+pub fn content_emojis(content: &str) -> HashMap<Emoji, usize> {
+    let custom_emoji = custom_emoji_pattern();
+
+    let mut counter = HashMap::<Emoji, usize>::new();
+    for captures in custom_emoji.captures_iter(content) {
+        if let Ok(id) = captures[3].parse::<u64>() {
+            let animated = !captures[1].is_empty();
+            let emoji = Emoji::custom(EmojiId::new(id), Some(captures[2].to_string()), animated);
+            *counter.entry(emoji).or_default() += 1;
+        }
+    }
+    let text = custom_emoji.replace_all(content, " ");
+    for grapheme in text.graphemes(true).filter(|g| is_emoji_sequence(g)) {
+        *counter.entry(grapheme.to_string().into()).or_default() += 1;
+    }
+    counter
+}
+
+/// Whether a grapheme cluster is drawn as an emoji. Characters that are text by
+/// default, such as ★ or ©, only count when followed by the emoji variation selector.
+fn is_emoji_sequence(grapheme: &str) -> bool {
+    let mut chars = grapheme.chars();
+    let first = match chars.next() {
+        Some(first) => first as u32,
+        None => return false,
+    };
+    if grapheme.contains('\u{FE0F}') || grapheme.contains('\u{20E3}') {
+        return true;
+    }
+    match first {
+        // two regional indicators make a flag, one alone is a letter
+        0x1F1E6..=0x1F1FF => chars.any(|c| matches!(c as u32, 0x1F1E6..=0x1F1FF)),
+        0x1F004
+        | 0x1F0CF
+        | 0x1F18E
+        | 0x1F191..=0x1F19A
+        | 0x1F201
+        | 0x1F21A
+        | 0x1F22F
+        | 0x1F232..=0x1F236
+        | 0x1F238..=0x1F23A
+        | 0x1F250..=0x1F251
+        | 0x1F300..=0x1F64F
+        | 0x1F680..=0x1F6FF
+        | 0x1F7E0..=0x1F7EB
+        | 0x1F7F0
+        | 0x1F90C..=0x1F9FF
+        | 0x1FA70..=0x1FAFF => true,
+        // emoji presentation characters of the Basic Multilingual Plane
+        0x231A..=0x231B
+        | 0x23E9..=0x23EC
+        | 0x23F0
+        | 0x23F3
+        | 0x25FD..=0x25FE
+        | 0x2614..=0x2615
+        | 0x2648..=0x2653
+        | 0x267F
+        | 0x2693
+        | 0x26A1
+        | 0x26AA..=0x26AB
+        | 0x26BD..=0x26BE
+        | 0x26C4..=0x26C5
+        | 0x26CE
+        | 0x26D4
+        | 0x26EA
+        | 0x26F2..=0x26F3
+        | 0x26F5
+        | 0x26FA
+        | 0x26FD
+        | 0x2705
+        | 0x270A..=0x270B
+        | 0x2728
+        | 0x274C
+        | 0x274E
+        | 0x2753..=0x2755
+        | 0x2757
+        | 0x2795..=0x2797
+        | 0x27B0
+        | 0x27BF
+        | 0x2B1B..=0x2B1C
+        | 0x2B50
+        | 0x2B55 => true,
+        _ => false,
+    }
 }
 
 impl From<EmojiId> for Emoji {
     fn from(id: EmojiId) -> Self {
-        Emoji::Custom(id)
+        Emoji::custom(id, None, false)
     }
 }
 
@@ -28,20 +256,38 @@ impl From<String> for Emoji {
 
 impl From<SerenityEmoji> for Emoji {
     fn from(value: SerenityEmoji) -> Self {
-        value.id.into()
+        Emoji::custom(value.id, Some(value.name), value.animated)
     }
 }
 
-impl From<ReactionType> for Emoji {
-    fn from(value: ReactionType) -> Self {
+impl TryFrom<ReactionType> for Emoji {
+    type Error = UnknownReaction;
+
+    fn try_from(value: ReactionType) -> Result<Self, Self::Error> {
         match value {
-            ReactionType::Custom { id, .. } => id.into(),
-            ReactionType::Unicode(c) => c.into(),
-            _ => unimplemented!(),
+            ReactionType::Custom { animated, id, name } => Ok(Emoji::custom(id, name, animated)),
+            ReactionType::Unicode(c) => Ok(c.into()),
+            other => Err(UnknownReaction(
+                serde_json::to_string(&other).unwrap_or_else(|_| format!("{:?}", other)),
+            )),
         }
     }
 }
 
+impl From<UnknownReaction> for Emoji {
+    fn from(value: UnknownReaction) -> Self {
+        Emoji::Unknown(value.0)
+    }
+}
+
+impl Display for UnknownReaction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown reaction type: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownReaction {}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[non_exhaustive]
 pub struct EmojiData {
@@ -64,3 +310,57 @@ impl From<SerenityEmoji> for EmojiData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(emoji: &Emoji) -> Emoji {
+        serde_json::from_str(&serde_json::to_string(emoji).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn keys_read_back_as_the_same_emoji() {
+        let emojis = [
+            Emoji::custom(EmojiId::new(123), Some("blob".into()), false),
+            Emoji::custom(EmojiId::new(456), Some("party".into()), true),
+            Emoji::custom(EmojiId::new(789), None, false),
+            Emoji::Unicode("👍🏽".into()),
+            Emoji::Unknown(r#"{"type":99}"#.into()),
+        ];
+        for emoji in &emojis {
+            let read = round_trip(emoji);
+            assert_eq!(&read, emoji);
+            assert_eq!(read.key(), emoji.key());
+        }
+    }
+
+    #[test]
+    fn older_keys_are_still_read() {
+        // custom emojis used to be stored as their id alone
+        assert_eq!(
+            Emoji::from_key("123".into()),
+            Emoji::custom(EmojiId::new(123), None, false)
+        );
+        assert_eq!(
+            Emoji::from_key("<:blob:123".into()),
+            Emoji::Unicode("<:blob:123".into())
+        );
+    }
+
+    #[test]
+    fn content_emojis_counts_custom_and_unicode_emojis() {
+        let counter =
+            content_emojis("<:blob:123> hi <a:party:456> 👍 👍 🇯🇵 ★ ★\u{FE0F} 1\u{FE0F}\u{20E3}");
+        let count = |emoji: Emoji| counter.get(&emoji).copied().unwrap_or_default();
+        assert_eq!(count(EmojiId::new(123).into()), 1);
+        assert_eq!(count(EmojiId::new(456).into()), 1);
+        assert_eq!(count(Emoji::Unicode("👍".into())), 2);
+        assert_eq!(count(Emoji::Unicode("🇯🇵".into())), 1);
+        // ★ is text unless followed by the emoji variation selector
+        assert_eq!(count(Emoji::Unicode("★".into())), 0);
+        assert_eq!(count(Emoji::Unicode("★\u{FE0F}".into())), 1);
+        assert_eq!(count(Emoji::Unicode("1\u{FE0F}\u{20E3}".into())), 1);
+        assert_eq!(counter.len(), 6);
+    }
+}
@@ -1,8 +1,9 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use serenity::all::{ChannelId, Message, MessageId, UserId};
+use serenity::all::{ChannelId, Message, MessageFlags, MessageId, MessageType, UserId};
 use std::collections::HashMap;
 
+use super::emoji::content_emojis;
 use super::Emoji;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -12,35 +13,257 @@ pub struct MessageData {
     pub message_id: MessageId,
     pub author_id: UserId,
     pub mentions: Vec<UserId>,
+    /// Normal reactions, without super reactions.
     pub reactions: HashMap<Emoji, u64>,
-    pub used_emojis: Vec<Emoji>,
+    /// Super reactions, also called burst reactions.
+    pub burst_reactions: HashMap<Emoji, u64>,
+    /// Users of the normal reactions.
+    pub reactors: HashMap<Emoji, Vec<UserId>>,
+    /// Users of the super reactions.
+    pub burst_reactors: HashMap<Emoji, Vec<UserId>>,
+    /// Emojis typed in the content, with how often each appears.
+    pub used_emojis: HashMap<Emoji, usize>,
     pub send_time: DateTime<Utc>,
     pub edit_time: Option<DateTime<Utc>>,
     pub attachment_count: usize,
     pub num_characters: usize,
     pub is_pinned: bool,
+    pub reference: Option<ReferenceData>,
+    /// Only stored for channels allowed with `CONTENT_CHANNELS`.
+    pub content: Option<String>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceKind {
+    Reply,
+    Forward,
+    ThreadStarter,
+    /// Crossposts, pin notifications and other system messages.
+    Other,
+}
+
+impl ReferenceKind {
+    /// Classifies the reference of a message of type `kind`. The pinned serenity
+    /// drops the reference type, but only forwards and crossposts reference
+    /// another message from a regular message, and crossposts are flagged.
+    fn of(kind: MessageType, flags: Option<MessageFlags>) -> Self {
+        match kind {
+            MessageType::InlineReply => Self::Reply,
+            MessageType::ThreadStarterMessage => Self::ThreadStarter,
+            MessageType::Regular
+                if !flags.is_some_and(|flags| flags.contains(MessageFlags::IS_CROSSPOST)) =>
+            {
+                Self::Forward
+            }
+            _ => Self::Other,
+        }
+    }
+}
+
+/// The message a message replies to, forwards or starts a thread from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[non_exhaustive]
+pub struct ReferenceData {
+    pub kind: ReferenceKind,
+    pub channel_id: ChannelId,
+    pub message_id: Option<MessageId>,
+    /// Author of the referenced message, unknown when it was deleted.
+    pub author_id: Option<UserId>,
+}
+
+impl MessageData {
+    /// Returns the author this message replies to.
+    pub fn replied_user(&self) -> Option<UserId> {
+        match &self.reference {
+            Some(reference) if reference.kind == ReferenceKind::Reply => reference.author_id,
+            _ => None,
+        }
+    }
+
+    /// Rewrites every user id the message refers to: author, mentions, reactors
+    /// and the author of the referenced message.
+    pub fn map_user_ids(&mut self, f: impl Fn(UserId) -> UserId) {
+        self.author_id = f(self.author_id);
+        for mention in &mut self.mentions {
+            *mention = f(*mention);
+        }
+        for users in self
+            .reactors
+            .values_mut()
+            .chain(self.burst_reactors.values_mut())
+        {
+            for user_id in users {
+                *user_id = f(*user_id);
+            }
+        }
+        if let Some(author_id) = self
+            .reference
+            .as_mut()
+            .and_then(|reference| reference.author_id.as_mut())
+        {
+            *author_id = f(*author_id);
+        }
+    }
+
+    /// Adds a reaction, and its user when known.
+    pub fn add_reaction(&mut self, emoji: Emoji, user_id: Option<UserId>, burst: bool) {
+        let (counts, reactors) = if burst {
+            (&mut self.burst_reactions, &mut self.burst_reactors)
+        } else {
+            (&mut self.reactions, &mut self.reactors)
+        };
+        *counts.entry(emoji.clone()).or_default() += 1;
+        if let Some(user_id) = user_id {
+            let users = reactors.entry(emoji).or_default();
+            if !users.contains(&user_id) {
+                users.push(user_id);
+            }
+        }
+    }
+
+    pub fn remove_reaction(&mut self, emoji: &Emoji, user_id: Option<UserId>, burst: bool) {
+        let (counts, reactors) = if burst {
+            (&mut self.burst_reactions, &mut self.burst_reactors)
+        } else {
+            (&mut self.reactions, &mut self.reactors)
+        };
+        if let Some(count) = counts.get_mut(emoji) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                counts.remove(emoji);
+            }
+        }
+        if let Some(user_id) = user_id {
+            if let Some(users) = reactors.get_mut(emoji) {
+                users.retain(|id| *id != user_id);
+                if users.is_empty() {
+                    reactors.remove(emoji);
+                }
+            }
+        }
+    }
+
+    /// Removes every reaction, or only the ones with `emoji`.
+    pub fn clear_reactions(&mut self, emoji: Option<&Emoji>) {
+        match emoji {
+            Some(emoji) => {
+                self.reactions.remove(emoji);
+                self.burst_reactions.remove(emoji);
+                self.reactors.remove(emoji);
+                self.burst_reactors.remove(emoji);
+            }
+            None => {
+                self.reactions.clear();
+                self.burst_reactions.clear();
+                self.reactors.clear();
+                self.burst_reactors.clear();
+            }
+        }
+    }
+}
+
 impl From<Message> for MessageData {
     fn from(message: Message) -> Self {
         let mut reactions = HashMap::<Emoji, u64>::new();
-        let mut used_emojis = Vec::<Emoji>::new();
+        let mut burst_reactions = HashMap::<Emoji, u64>::new();
         for reaction in &message.reactions {
-            let emoji: Emoji = reaction.reaction_type.clone().into();
-            reactions.insert(emoji.clone(), reaction.count);
-            used_emojis.push(emoji);
+            let emoji = Emoji::from_reaction(reaction.reaction_type.clone());
+            if reaction.count_details.normal > 0 {
+                reactions.insert(emoji.clone(), reaction.count_details.normal);
+            }
+            if reaction.count_details.burst > 0 {
+                burst_reactions.insert(emoji, reaction.count_details.burst);
+            }
         }
+        let reference = message
+            .message_reference
+            .as_ref()
+            .map(|reference| ReferenceData {
+                kind: ReferenceKind::of(message.kind, message.flags),
+                channel_id: reference.channel_id,
+                message_id: reference.message_id,
+                author_id: message
+                    .referenced_message
+                    .as_ref()
+                    .map(|referenced| referenced.author.id),
+            });
         Self {
             channel_id: message.channel_id,
             message_id: message.id,
             mentions: message.mentions.iter().map(|mention| mention.id).collect(),
             author_id: message.author.id,
             reactions,
-            used_emojis,
+            burst_reactions,
+            reactors: HashMap::new(),
+            burst_reactors: HashMap::new(),
+            used_emojis: content_emojis(&message.content),
             send_time: *message.timestamp,
             edit_time: message.edited_timestamp.map(|timestamp| *timestamp),
             attachment_count: message.attachments.len(),
             num_characters: message.content.chars().count(),
             is_pinned: message.pinned,
+            reference,
+            content: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl MessageData {
+    /// A message with nothing but its ids, sent at the Unix epoch plus `message_id` seconds.
+    pub(crate) fn for_test(channel_id: u64, message_id: u64, author_id: u64) -> Self {
+        Self {
+            channel_id: ChannelId::new(channel_id),
+            message_id: MessageId::new(message_id),
+            author_id: UserId::new(author_id),
+            mentions: Vec::new(),
+            reactions: HashMap::new(),
+            burst_reactions: HashMap::new(),
+            reactors: HashMap::new(),
+            burst_reactors: HashMap::new(),
+            used_emojis: HashMap::new(),
+            send_time: DateTime::from_timestamp(message_id as i64, 0).unwrap(),
+            edit_time: None,
+            attachment_count: 0,
+            num_characters: 0,
+            is_pinned: false,
+            reference: None,
+            content: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_are_classified_by_message_type_and_flags() {
+        let crosspost = Some(MessageFlags::IS_CROSSPOST);
+        assert_eq!(
+            ReferenceKind::of(MessageType::InlineReply, None),
+            ReferenceKind::Reply
+        );
+        assert_eq!(
+            ReferenceKind::of(MessageType::ThreadStarterMessage, None),
+            ReferenceKind::ThreadStarter
+        );
+        assert_eq!(
+            ReferenceKind::of(MessageType::Regular, None),
+            ReferenceKind::Forward
+        );
+        assert_eq!(
+            ReferenceKind::of(MessageType::Regular, Some(MessageFlags::empty())),
+            ReferenceKind::Forward
+        );
+        assert_eq!(
+            ReferenceKind::of(MessageType::Regular, crosspost),
+            ReferenceKind::Other
+        );
+        assert_eq!(
+            ReferenceKind::of(MessageType::PinsAdd, None),
+            ReferenceKind::Other
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use crate::error::{Error, Result};
+use serde_json::{Map, Value};
+
+/// Schema version written by this build.
+pub const SCHEMA_VERSION: u32 = 8;
+
+type Object = Map<String, Value>;
+
+/// Version that added `fetched_until`. Archives split apart from their
+/// messages, such as the sharded one, fill it themselves when upgrading from before it.
+pub(crate) const FETCHED_UNTIL_VERSION: u32 = 7;
+
+/// Printed when an archive from before version 3 is upgraded, see [`v2_to_v3_message`].
+pub(crate) const USED_EMOJIS_RESET: &str = "Warning: the archive predates counting the emojis \
+    typed in messages, so they start at zero. Run the getter with --full to count them.";
+
+/// Upgrades one schema version. `metadata` sees the archive root, `message` sees each message.
+struct Migration {
+    metadata: fn(&mut Object),
+    message: fn(&mut Object),
+}
+
+/// `MIGRATIONS[n]` upgrades an archive from version `n` to `n + 1`.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+    Migration {
+        metadata: v0_to_v1_metadata,
+        message: v0_to_v1_message,
+    },
+    Migration {
+        metadata: no_metadata_change,
+        message: v1_to_v2_message,
+    },
+    Migration {
+        metadata: no_metadata_change,
+        message: v2_to_v3_message,
+    },
+    Migration {
+        metadata: no_metadata_change,
+        message: v3_to_v4_message,
+    },
+    Migration {
+        metadata: no_metadata_change,
+        message: v4_to_v5_message,
+    },
+    Migration {
+        metadata: v5_to_v6_metadata,
+        message: no_message_change,
+    },
+    Migration {
+        metadata: v6_to_v7_metadata,
+        message: no_message_change,
+    },
+    Migration {
+        metadata: no_metadata_change,
+        message: v7_to_v8_message,
+    },
+];
+
+/// Reads the schema version of an archive root, failing if it is newer than this build.
+pub fn version(root: &Value) -> Result<u32> {
+    let version = match root.get("version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or(Error::InvalidArchive("version is not a number"))?
+            as u32,
+        None => 0,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(Error::NewerSchema {
+            found: version as usize,
+            supported: SCHEMA_VERSION as usize,
+        });
+    }
+    Ok(version)
+}
+
+/// Upgrades a whole archive, messages included.
+pub fn migrate(mut value: Value) -> Result<Value> {
+    let version = version(&value)?;
+    let root = value
+        .as_object_mut()
+        .ok_or(Error::InvalidArchive("the archive is not a JSON object"))?;
+    if version < 3 {
+        eprintln!("{}", USED_EMOJIS_RESET);
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        (migration.metadata)(root);
+        for message in messages_mut(root) {
+            (migration.message)(message);
+        }
+    }
+    root.insert("version".to_string(), SCHEMA_VERSION.into());
+    Ok(value)
+}
+
+/// Upgrades a single message stored with the given schema version.
+pub fn migrate_message(mut value: Value, version: u32) -> Result<Value> {
+    let message = value
+        .as_object_mut()
+        .ok_or(Error::InvalidArchive("a message is not a JSON object"))?;
+    for migration in &MIGRATIONS[version as usize..] {
+        (migration.message)(message);
+    }
+    Ok(value)
+}
+
+fn entries_mut<'a>(root: &'a mut Object, key: &str) -> impl Iterator<Item = &'a mut Object> {
+    root.get_mut(key)
+        .and_then(Value::as_object_mut)
+        .into_iter()
+        .flat_map(|map| map.values_mut())
+        .filter_map(Value::as_object_mut)
+}
+
+fn messages_mut(root: &mut Object) -> impl Iterator<Item = &mut Object> {
+    root.get_mut("messages")
+        .and_then(Value::as_object_mut)
+        .into_iter()
+        .flat_map(|map| map.values_mut())
+        .filter_map(Value::as_array_mut)
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+fn insert_default(entry: &mut Object, key: &str, default: Value) {
+    entry.entry(key).or_insert(default);
+}
+
+/// Unversioned archives may lack fields added before versioning existed.
+fn v0_to_v1_metadata(root: &mut Object) {
+    for member in entries_mut(root, "members") {
+        insert_default(member, "is_bot", Value::Bool(false));
+    }
+    for channel in entries_mut(root, "channels") {
+        insert_default(channel, "parent_id", Value::Null);
+    }
+}
+
+fn v0_to_v1_message(message: &mut Object) {
+    insert_default(message, "reactors", Value::Object(Map::new()));
+}
+
+fn no_metadata_change(_root: &mut Object) {}
+
+fn no_message_change(_message: &mut Object) {}
+
+/// Messages gained the replied, forwarded or thread starter message.
+fn v1_to_v2_message(message: &mut Object) {
+    insert_default(message, "reference", Value::Null);
+}
+
+/// `used_emojis` listed the reaction emojis again. It now counts the emojis
+/// typed in the content, which archives only keep since version 5, so it
+/// starts empty until the getter fetches the messages again with `--full`.
+/// The old list is an array, so counts already in the new form are kept.
+fn v2_to_v3_message(message: &mut Object) {
+    if !message.get("used_emojis").is_some_and(Value::is_object) {
+        message.insert("used_emojis".to_string(), Value::Object(Map::new()));
+    }
+}
+
+/// Super reactions got their own counts. Older archives have them inside `reactions`.
+fn v3_to_v4_message(message: &mut Object) {
+    insert_default(message, "burst_reactions", Value::Object(Map::new()));
+}
+
+/// Content became storable for allowed channels.
+fn v4_to_v5_message(message: &mut Object) {
+    insert_default(message, "content", Value::Null);
+}
+
+/// Forgotten users are kept so that refetched data is forgotten again.
+fn v5_to_v6_metadata(root: &mut Object) {
+    insert_default(root, "forgotten_users", Value::Object(Map::new()));
+}
+
+/// The getter keeps where it stopped apart from the recorded messages. It used
+/// to stop at the newest stored message, so that is where it carries on from.
+/// This is the migration to [`FETCHED_UNTIL_VERSION`].
+fn v6_to_v7_metadata(root: &mut Object) {
+    let fetched_until: Object = root
+        .get("messages")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .filter_map(|(channel_id, messages)| {
+            let newest = messages
+                .as_array()?
+                .iter()
+                .filter_map(|message| snowflake(message.get("message_id")?))
+                .max()?;
+            Some((channel_id.clone(), Value::String(newest.to_string())))
+        })
+        .collect();
+    insert_default(root, "fetched_until", Value::Object(fetched_until));
+}
+
+/// Users of super reactions got their own lists. They were not fetched before.
+fn v7_to_v8_message(message: &mut Object) {
+    insert_default(message, "burst_reactors", Value::Object(Map::new()));
+}
+
+/// Ids are written as strings, but serenity also reads them as numbers.
+fn snowflake(id: &Value) -> Option<u64> {
+    match id {
+        Value::String(id) => id.parse().ok(),
+        id => id.as_u64(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+    use serenity::all::{ChannelId, ChannelType, GuildId, MessageId, UserId};
+
+    use super::*;
+    use crate::message_data::{ChannelData, JsonData, MessageData, UserData};
+
+    const CHANNEL: u64 = 2;
+    const USER: u64 = 3;
+
+    fn current_archive() -> Value {
+        let user = UserData::new(UserId::new(USER), "name".into(), "name".into(), None, true);
+        let channel = ChannelData::new(
+            ChannelId::new(CHANNEL),
+            "general".into(),
+            ChannelType::Text,
+            vec![],
+            Some(ChannelId::new(4)),
+        );
+        let messages = vec![
+            MessageData::for_test(CHANNEL, 9, USER),
+            MessageData::for_test(CHANNEL, 8, USER),
+        ];
+        let data = JsonData::new(
+            GuildId::new(1),
+            HashMap::from([(user.user_id, user)]),
+            HashMap::from([(channel.channel_id, channel)]),
+            HashMap::new(),
+            HashMap::from([(ChannelId::new(CHANNEL), messages)]),
+        );
+        serde_json::to_value(data).unwrap()
+    }
+
+    /// Removes what every migration added, as an archive written before versioning.
+    fn unversioned_archive() -> Value {
+        let mut value = current_archive();
+        let root = value.as_object_mut().unwrap();
+        for key in ["version", "fetched_until", "forgotten_users"] {
+            root.remove(key);
+        }
+        for member in entries_mut(root, "members") {
+            member.remove("is_bot");
+        }
+        for channel in entries_mut(root, "channels") {
+            channel.remove("parent_id");
+        }
+        for message in messages_mut(root) {
+            for key in [
+                "reactors",
+                "reference",
+                "burst_reactions",
+                "content",
+                "burst_reactors",
+            ] {
+                message.remove(key);
+            }
+            // the reaction emojis were listed here before version 3
+            message.insert("used_emojis".to_string(), json!(["👍"]));
+        }
+        value
+    }
+
+    #[test]
+    fn unversioned_archives_are_upgraded() {
+        let data: JsonData =
+            serde_json::from_value(migrate(unversioned_archive()).unwrap()).unwrap();
+        assert_eq!(data.version, SCHEMA_VERSION);
+        assert!(!data.members[&UserId::new(USER)].is_bot);
+        assert_eq!(data.channels[&ChannelId::new(CHANNEL)].parent_id, None);
+        for message in &data.messages[&ChannelId::new(CHANNEL)] {
+            assert!(message.used_emojis.is_empty());
+            assert!(message.reference.is_none());
+            assert!(message.content.is_none());
+        }
+        // the getter carries on from the newest stored message
+        assert_eq!(
+            data.fetched_until[&ChannelId::new(CHANNEL)],
+            MessageId::new(9)
+        );
+        assert!(data.forgotten_users.is_empty());
+    }
+
+    #[test]
+    fn current_archives_are_left_as_is() {
+        let value = current_archive();
+        assert_eq!(migrate(value.clone()).unwrap(), value);
+    }
+
+    #[test]
+    fn messages_are_upgraded_alone() {
+        let mut message = serde_json::to_value(MessageData::for_test(CHANNEL, 9, USER)).unwrap();
+        message.as_object_mut().unwrap().remove("content");
+        let message = migrate_message(message, 4).unwrap();
+        assert_eq!(message["content"], Value::Null);
+    }
+
+    #[test]
+    fn emoji_counts_are_only_reset_once() {
+        let mut message = serde_json::to_value(MessageData::for_test(CHANNEL, 9, USER)).unwrap();
+        message["used_emojis"] = json!({ "👍": 1 });
+        // a message upgraded again after an interrupted upgrade keeps its counts
+        let message = migrate_message(message, 2).unwrap();
+        assert_eq!(message["used_emojis"], json!({ "👍": 1 }));
+    }
+
+    #[test]
+    fn newer_archives_are_refused() {
+        let value = json!({ "version": SCHEMA_VERSION + 1 });
+        assert!(matches!(migrate(value), Err(Error::NewerSchema { .. })));
+    }
+}
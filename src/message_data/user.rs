@@ -10,7 +10,6 @@ pub struct UserData {
     pub username: String,
     pub display_name: String,
     pub avatar_url: Option<String>,
-    #[serde(default)]
     pub is_bot: bool,
 }
 
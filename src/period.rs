@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{Datelike, Days, Months, NaiveDate};
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+
+/// Size of the calendar buckets a [`TimeSeries`] counts in.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Day,
+    /// ISO 8601 weeks, starting on Monday.
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+/// One calendar bucket, identified by its first day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Period {
+    pub granularity: Granularity,
+    pub start: NaiveDate,
+}
+
+/// Values bucketed by period. Only periods that were written to are stored.
+#[derive(Debug, Clone)]
+pub struct TimeSeries<T> {
+    granularity: Granularity,
+    buckets: BTreeMap<Period, T>,
+}
+
+impl Period {
+    pub fn containing(date: NaiveDate, granularity: Granularity) -> Self {
+        let start = match granularity {
+            Granularity::Day => date,
+            Granularity::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+            Granularity::Month => date.with_day(1).unwrap(),
+            Granularity::Quarter => {
+                let month = (date.month0() / 3) * 3 + 1;
+                NaiveDate::from_ymd_opt(date.year(), month, 1).unwrap()
+            }
+            Granularity::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+        };
+        Self { granularity, start }
+    }
+
+    pub fn next(self) -> Self {
+        let start = match self.granularity {
+            Granularity::Day => self.start + Days::new(1),
+            Granularity::Week => self.start + Days::new(7),
+            Granularity::Month => self.start + Months::new(1),
+            Granularity::Quarter => self.start + Months::new(3),
+            Granularity::Year => self.start + Months::new(12),
+        };
+        Self { start, ..self }
+    }
+
+    /// Last day of the period.
+    pub fn end(self) -> NaiveDate {
+        self.next().start - Days::new(1)
+    }
+
+    /// Every period from the one containing `since` to the one containing `until`.
+    pub fn range(
+        since: NaiveDate,
+        until: NaiveDate,
+        granularity: Granularity,
+    ) -> impl Iterator<Item = Period> {
+        let last = Self::containing(until, granularity);
+        std::iter::successors(Some(Self::containing(since, granularity)), |period| {
+            Some(period.next())
+        })
+        .take_while(move |period| *period <= last)
+    }
+}
+
+impl<T: Default> TimeSeries<T> {
+    pub fn new(granularity: Granularity) -> Self {
+        Self {
+            granularity,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    pub fn granularity(&self) -> Granularity {
+        self.granularity
+    }
+
+    /// Returns the value of the period containing `date`, inserting a default one.
+    pub fn bucket_mut(&mut self, date: NaiveDate) -> &mut T {
+        self.buckets
+            .entry(Period::containing(date, self.granularity))
+            .or_default()
+    }
+
+    pub fn get(&self, period: &Period) -> Option<&T> {
+        self.buckets.get(period)
+    }
+
+    /// Stored periods in chronological order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Period, &T)> {
+        self.buckets.iter()
+    }
+
+    /// Every period between the two dates, including ones without a value.
+    pub fn range(
+        &self,
+        since: NaiveDate,
+        until: NaiveDate,
+    ) -> impl Iterator<Item = (Period, Option<&T>)> {
+        Period::range(since, until, self.granularity).map(|period| (period, self.get(&period)))
+    }
+}
+
+impl Display for Period {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let start = self.start;
+        match self.granularity {
+            Granularity::Day => write!(f, "{}", start.format("%Y-%m-%d")),
+            Granularity::Week => {
+                let week = start.iso_week();
+                write!(f, "{}-W{:02}", week.year(), week.week())
+            }
+            Granularity::Month => write!(f, "{}", start.format("%Y-%m")),
+            Granularity::Quarter => write!(f, "{}-Q{}", start.year(), start.month0() / 3 + 1),
+            Granularity::Year => write!(f, "{}", start.year()),
+        }
+    }
+}
+
+impl FromStr for Granularity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "day" => Ok(Self::Day),
+            "week" => Ok(Self::Week),
+            "month" => Ok(Self::Month),
+            "quarter" => Ok(Self::Quarter),
+            "year" => Ok(Self::Year),
+            _ => Err(Error::UnknownValue {
+                kind: "granularity",
+                value: s.to_string(),
+                expected: "day, week, month, quarter or year",
+            }),
+        }
+    }
+}
+
+impl Display for Granularity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+            Self::Quarter => "quarter",
+            Self::Year => "year",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn weeks_start_on_monday_across_years() {
+        let week = Period::containing(date(2024, 12, 31), Granularity::Week);
+        assert_eq!(week.start, date(2024, 12, 30));
+        assert_eq!(week.end(), date(2025, 1, 5));
+        // ISO weeks belong to the year of their Thursday
+        assert_eq!(week.to_string(), "2025-W01");
+        assert_eq!(
+            Period::containing(date(2025, 1, 5), Granularity::Week),
+            week
+        );
+        assert_eq!(
+            Period::containing(date(2025, 1, 6), Granularity::Week),
+            week.next()
+        );
+    }
+
+    #[test]
+    fn months_and_quarters_end_on_their_last_day() {
+        let february = Period::containing(date(2024, 2, 29), Granularity::Month);
+        assert_eq!(february.start, date(2024, 2, 1));
+        assert_eq!(february.end(), date(2024, 2, 29));
+        assert_eq!(february.to_string(), "2024-02");
+
+        let january = Period::containing(date(2025, 1, 31), Granularity::Month);
+        assert_eq!(january.next().start, date(2025, 2, 1));
+        assert_eq!(january.end(), date(2025, 1, 31));
+
+        let quarter = Period::containing(date(2024, 12, 31), Granularity::Quarter);
+        assert_eq!(quarter.start, date(2024, 10, 1));
+        assert_eq!(quarter.next().start, date(2025, 1, 1));
+        assert_eq!(quarter.to_string(), "2024-Q4");
+    }
+
+    #[test]
+    fn buckets_are_in_chronological_order() {
+        let mut series = TimeSeries::<usize>::new(Granularity::Month);
+        for day in [date(2024, 3, 15), date(2023, 12, 1), date(2024, 3, 1)] {
+            *series.bucket_mut(day) += 1;
+        }
+        let buckets: Vec<_> = series
+            .iter()
+            .map(|(period, count)| (period.to_string(), *count))
+            .collect();
+        assert_eq!(
+            buckets,
+            [("2023-12".to_string(), 1), ("2024-03".to_string(), 2)]
+        );
+
+        let range: Vec<_> = series
+            .range(date(2023, 12, 31), date(2024, 3, 1))
+            .map(|(period, count)| (period.to_string(), count.copied()))
+            .collect();
+        assert_eq!(
+            range,
+            [
+                ("2023-12".to_string(), Some(1)),
+                ("2024-01".to_string(), None),
+                ("2024-02".to_string(), None),
+                ("2024-03".to_string(), Some(2)),
+            ]
+        );
+    }
+}
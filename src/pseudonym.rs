@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use hmac::{Hmac, Mac};
+use regex::{Captures, Regex};
+use serenity::all::{ChannelId, GuildId, MessageId, PermissionOverwriteType, RoleId, UserId};
+use sha2::Sha256;
+
+use crate::message_data::{JsonData, UserData};
+use crate::period::{Granularity, Period};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How an archive is rewritten before it is shared.
+#[derive(Debug, Clone)]
+pub struct PseudonymOptions {
+    /// Secret the pseudonyms are derived from. The same key gives the same
+    /// pseudonyms, so exports made with it can be compared.
+    pub key: Vec<u8>,
+    /// Moves send and edit times to the start of their period in `timezone`.
+    pub coarsen: Option<Granularity>,
+    pub timezone: Tz,
+    pub keep_content: bool,
+}
+
+/// Replaces every user, role and guild id with a keyed hash and the names with
+/// one derived from it, and drops avatars. Message ids encode the send time
+/// and link to the original message, so they are renumbered in send order.
+/// Relations between users and messages are kept, and so are mentions in kept
+/// content.
+pub fn pseudonymize(mut data: JsonData, options: &PseudonymOptions) -> JsonData {
+    let mac = HmacSha256::new_from_slice(&options.key).expect("HMAC accepts keys of any length");
+    let keyed_id = |tag: &[u8], id: u64| {
+        let mut mac = mac.clone();
+        mac.update(tag);
+        mac.update(&id.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        // stay below i64::MAX so the id also fits SQLite integers
+        (u64::from_be_bytes(bytes) >> 1).max(1)
+    };
+    // user ids are hashed without a tag, which keeps them equal to older exports
+    let pseudonym = |user_id: UserId| UserId::new(keyed_id(b"", user_id.get()));
+    let guild_id = GuildId::new(keyed_id(b"guild", data.guild_id.get()));
+    // the @everyone role shares the guild id, and must keep sharing it
+    let original_guild_id = data.guild_id;
+    let role_pseudonym = |role_id: RoleId| {
+        if role_id.get() == original_guild_id.get() {
+            RoleId::new(guild_id.get())
+        } else {
+            RoleId::new(keyed_id(b"role", role_id.get()))
+        }
+    };
+    data.guild_id = guild_id;
+    // forgotten users and crawl progress only matter to the archive, and use real ids
+    data.forgotten_users.clear();
+    data.fetched_until.clear();
+
+    for channel in data.channels.values_mut() {
+        for overwrite in &mut channel.permission_overwrites {
+            overwrite.kind = match overwrite.kind {
+                PermissionOverwriteType::Member(user_id) => {
+                    PermissionOverwriteType::Member(pseudonym(user_id))
+                }
+                PermissionOverwriteType::Role(role_id) => {
+                    PermissionOverwriteType::Role(role_pseudonym(role_id))
+                }
+                other => other,
+            };
+        }
+    }
+
+    data.members = data
+        .members
+        .into_values()
+        .map(|user| {
+            let user_id = pseudonym(user.user_id);
+            let name = format!("user-{:016x}", user_id.get());
+            let user = UserData::new(user_id, name.clone(), name, None, user.is_bot);
+            (user_id, user)
+        })
+        .collect();
+
+    let message_ids = renumbered_message_ids(&data);
+    for messages in data.messages.values_mut() {
+        for message in messages.iter_mut() {
+            message.map_user_ids(pseudonym);
+            message.content = match message.content.take() {
+                Some(content) if options.keep_content => Some(rewrite_mentions(
+                    &content,
+                    pseudonym,
+                    role_pseudonym,
+                    |channel_id| data.channels.contains_key(&ChannelId::new(channel_id)),
+                )),
+                _ => None,
+            };
+            message.message_id = message_ids[&message.message_id];
+            if let Some(reference) = &mut message.reference {
+                // a message outside the archive would keep its real id
+                reference.message_id = reference
+                    .message_id
+                    .and_then(|message_id| message_ids.get(&message_id).copied());
+            }
+            if let Some(granularity) = options.coarsen {
+                message.send_time = coarsen(message.send_time, granularity, &options.timezone);
+                message.edit_time = message
+                    .edit_time
+                    .map(|time| coarsen(time, granularity, &options.timezone));
+            }
+        }
+    }
+    data
+}
+
+fn mention_pattern() -> &'static Regex {
+    static MENTION: OnceLock<Regex> = OnceLock::new();
+    MENTION.get_or_init(|| Regex::new(r"<(@!?|@&|#)(\d+)>").unwrap())
+}
+
+/// Rewrites user and role mentions with their pseudonyms. Channel ids are
+/// kept by the export, so only mentions of channels outside it are dropped.
+fn rewrite_mentions(
+    content: &str,
+    pseudonym: impl Fn(UserId) -> UserId,
+    role_pseudonym: impl Fn(RoleId) -> RoleId,
+    is_exported_channel: impl Fn(u64) -> bool,
+) -> String {
+    mention_pattern()
+        .replace_all(content, |captures: &Captures| {
+            let id = match captures[2].parse::<u64>() {
+                Ok(id) if id != 0 => id,
+                _ => return "<@0>".to_string(),
+            };
+            match &captures[1] {
+                "@&" => format!("<@&{}>", role_pseudonym(RoleId::new(id))),
+                "#" if is_exported_channel(id) => format!("<#{}>", id),
+                "#" => "#unknown-channel".to_string(),
+                _ => format!("<@{}>", pseudonym(UserId::new(id))),
+            }
+        })
+        .into_owned()
+}
+
+/// Numbers the archived messages from 1 in the order they were sent.
+fn renumbered_message_ids(data: &JsonData) -> HashMap<MessageId, MessageId> {
+    let mut message_ids: Vec<MessageId> = data
+        .messages
+        .values()
+        .flatten()
+        .map(|message| message.message_id)
+        .collect();
+    message_ids.sort();
+    message_ids
+        .into_iter()
+        .enumerate()
+        .map(|(i, message_id)| (message_id, MessageId::new(i as u64 + 1)))
+        .collect()
+}
+
+fn coarsen(time: DateTime<Utc>, granularity: Granularity, timezone: &Tz) -> DateTime<Utc> {
+    let date = time.with_timezone(timezone).date_naive();
+    let start = Period::containing(date, granularity)
+        .start
+        .and_time(NaiveTime::MIN);
+    match timezone.from_local_datetime(&start).earliest() {
+        Some(start) => start.with_timezone(&Utc),
+        // midnight skipped by a daylight saving change
+        None => Utc.from_utc_datetime(&start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serenity::all::{
+        ChannelId, ChannelType, GuildId, PermissionOverwrite, PermissionOverwriteType, Permissions,
+        RoleId, UserId,
+    };
+
+    use super::*;
+    use crate::forget::ForgetMode;
+    use crate::message_data::{ChannelData, MessageData, ReferenceData, ReferenceKind};
+
+    const GUILD: u64 = 111111111111111111;
+    const ALICE: u64 = 222222222222222222;
+    const BOB: u64 = 333333333333333333;
+    const CAROL: u64 = 444444444444444444;
+    const ROLE: u64 = 555555555555555555;
+    const CHANNEL: u64 = 666666666666666666;
+    const OTHER_CHANNEL: u64 = 777777777777777777;
+    const OTHER_USER: u64 = 888888888888888888;
+    const MESSAGE: u64 = 999999999999999999;
+    const OTHER_MESSAGE: u64 = 1010101010101010101;
+
+    fn options(keep_content: bool) -> PseudonymOptions {
+        PseudonymOptions {
+            key: b"secret".to_vec(),
+            coarsen: None,
+            timezone: Tz::UTC,
+            keep_content,
+        }
+    }
+
+    fn overwrite(kind: PermissionOverwriteType) -> PermissionOverwrite {
+        PermissionOverwrite {
+            allow: Permissions::empty(),
+            deny: Permissions::VIEW_CHANNEL,
+            kind,
+        }
+    }
+
+    fn archive() -> JsonData {
+        let members = [ALICE, BOB]
+            .into_iter()
+            .map(|id| {
+                let user_id = UserId::new(id);
+                let user = UserData::new(user_id, "name".into(), "name".into(), None, false);
+                (user_id, user)
+            })
+            .collect();
+        let channel = ChannelData::new(
+            ChannelId::new(CHANNEL),
+            "general".into(),
+            ChannelType::Text,
+            vec![
+                overwrite(PermissionOverwriteType::Member(UserId::new(CAROL))),
+                overwrite(PermissionOverwriteType::Role(RoleId::new(ROLE))),
+                overwrite(PermissionOverwriteType::Role(RoleId::new(GUILD))),
+            ],
+            None,
+        );
+        let mut message = MessageData::for_test(CHANNEL, 1, ALICE);
+        message.message_id = MessageId::new(MESSAGE);
+        message.mentions = vec![UserId::new(BOB)];
+        message.content = Some(format!(
+            "<@{BOB}> <@!{CAROL}> <@&{ROLE}> <#{CHANNEL}> <#{OTHER_CHANNEL}>"
+        ));
+        message.reference = Some(ReferenceData {
+            kind: ReferenceKind::Reply,
+            channel_id: ChannelId::new(CHANNEL),
+            message_id: Some(MessageId::new(OTHER_MESSAGE)),
+            author_id: Some(UserId::new(CAROL)),
+        });
+        let mut data = JsonData::new(
+            GuildId::new(GUILD),
+            members,
+            HashMap::from([(channel.channel_id, channel)]),
+            HashMap::new(),
+            HashMap::from([(ChannelId::new(CHANNEL), vec![message])]),
+        );
+        data.forgotten_users
+            .insert(UserId::new(OTHER_USER), ForgetMode::Remove);
+        data.fetched_until
+            .insert(ChannelId::new(CHANNEL), MessageId::new(MESSAGE));
+        data
+    }
+
+    #[test]
+    fn no_original_id_is_exported() {
+        let data = pseudonymize(archive(), &options(true));
+        let json = serde_json::to_string(&data).unwrap();
+        let ids = [
+            GUILD,
+            ALICE,
+            BOB,
+            CAROL,
+            ROLE,
+            OTHER_CHANNEL,
+            OTHER_USER,
+            MESSAGE,
+            OTHER_MESSAGE,
+        ];
+        for id in ids {
+            assert!(!json.contains(&id.to_string()), "{} was exported", id);
+        }
+    }
+
+    #[test]
+    fn mentions_and_overwrites_use_the_member_pseudonyms() {
+        let data = pseudonymize(archive(), &options(true));
+        let message = &data.messages[&ChannelId::new(CHANNEL)][0];
+        let bob = message.mentions[0];
+        let carol = message.reference.as_ref().unwrap().author_id.unwrap();
+        let content = message.content.as_deref().unwrap();
+        assert!(content.starts_with(&format!("<@{}> <@{}> <@&", bob, carol)));
+        assert!(content.ends_with(&format!("<#{}> #unknown-channel", CHANNEL)));
+
+        let overwrites = &data.channels[&ChannelId::new(CHANNEL)].permission_overwrites;
+        assert_eq!(overwrites[0].kind, PermissionOverwriteType::Member(carol));
+        // @everyone keeps matching the guild, so private channels stay recognizable
+        assert_eq!(
+            overwrites[2].kind,
+            PermissionOverwriteType::Role(RoleId::new(data.guild_id.get()))
+        );
+    }
+
+    #[test]
+    fn content_is_dropped_unless_kept() {
+        let data = pseudonymize(archive(), &options(false));
+        assert!(data.messages[&ChannelId::new(CHANNEL)][0].content.is_none());
+    }
+
+    #[test]
+    fn pseudonyms_depend_on_the_key_only() {
+        let first = pseudonymize(archive(), &options(false));
+        let second = pseudonymize(archive(), &options(false));
+        let mut keys: Vec<_> = first.members.keys().collect();
+        keys.sort();
+        let mut other: Vec<_> = second.members.keys().collect();
+        other.sort();
+        assert_eq!(keys, other);
+        assert_eq!(first.guild_id, second.guild_id);
+
+        let other_key = PseudonymOptions {
+            key: b"other secret".to_vec(),
+            ..options(false)
+        };
+        let third = pseudonymize(archive(), &other_key);
+        assert!(third.members.keys().all(|user_id| !keys.contains(&user_id)));
+        assert_ne!(first.guild_id, third.guild_id);
+    }
+
+    #[test]
+    fn messages_are_renumbered_in_send_order() {
+        let mut data = archive();
+        let messages = data.messages.get_mut(&ChannelId::new(CHANNEL)).unwrap();
+        let mut reply = MessageData::for_test(CHANNEL, 2, BOB);
+        reply.message_id = MessageId::new(MESSAGE + 1);
+        reply.reference = Some(ReferenceData {
+            kind: ReferenceKind::Reply,
+            channel_id: ChannelId::new(CHANNEL),
+            message_id: Some(MessageId::new(MESSAGE)),
+            author_id: Some(UserId::new(ALICE)),
+        });
+        messages.push(reply);
+
+        let data = pseudonymize(data, &options(false));
+        let messages = &data.messages[&ChannelId::new(CHANNEL)];
+        assert_eq!(messages[0].message_id, MessageId::new(1));
+        assert_eq!(messages[1].message_id, MessageId::new(2));
+        // references outside the archive are dropped, the others follow the renumbering
+        assert_eq!(messages[0].reference.as_ref().unwrap().message_id, None);
+        assert_eq!(
+            messages[1].reference.as_ref().unwrap().message_id,
+            Some(MessageId::new(1))
+        );
+        assert!(data.fetched_until.is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+mod csv;
+mod markdown;
+mod text;
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use itertools::Itertools;
+use serde::Serialize;
+use serenity::all::ChannelId;
+
+use crate::error::{Error, Result};
+use crate::message_data::ChannelData;
+use crate::period::Granularity;
+use crate::stats::ChannelCounter;
+
+/// Aggregation results of calc, independent of how they are printed.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Report {
+    pub periods: Vec<PeriodReport>,
+    /// Sections covering the whole reported range instead of a single year.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<Section>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trend: Option<Trend>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PeriodReport {
+    pub year: i32,
+    pub messages: usize,
+    pub mentions: usize,
+    pub sections: Vec<Section>,
+}
+
+/// Totals per day, week, month, quarter or year.
+#[derive(Serialize, Debug, Clone)]
+pub struct Trend {
+    pub granularity: Granularity,
+    pub rows: Vec<TrendRow>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TrendRow {
+    pub period: String,
+    pub messages: usize,
+    pub mentions: usize,
+    pub reactions: usize,
+}
+
+/// A ranking such as "message count".
+#[derive(Serialize, Debug, Clone)]
+pub struct Section {
+    pub title: String,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Entry {
+    pub rank: Option<usize>,
+    pub name: String,
+    pub count: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<ChannelShare>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Share of an entry's count that came from one channel.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChannelShare {
+    pub name: String,
+    pub percent: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+    Markdown,
+}
+
+impl Section {
+    pub fn new(title: impl Into<String>, entries: Vec<Entry>) -> Self {
+        Self {
+            title: title.into(),
+            entries,
+        }
+    }
+}
+
+impl Entry {
+    pub fn new(rank: Option<usize>, name: impl Into<String>, count: usize) -> Self {
+        Self {
+            rank,
+            name: name.into(),
+            count,
+            channels: Vec::new(),
+            note: None,
+        }
+    }
+
+    pub fn with_channels(mut self, channels: Vec<ChannelShare>) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+}
+
+impl ChannelShare {
+    /// Splits `sum` over the channels of `counter`, largest share first.
+    pub fn from_counter(
+        counter: &ChannelCounter,
+        channels: &HashMap<ChannelId, ChannelData>,
+        sum: usize,
+    ) -> Vec<Self> {
+        counter
+            .iter()
+            .map(|(channel_id, count)| {
+                (channel_id, ((*count as f64 / sum as f64) * 100.0) as usize)
+            })
+            .sorted_by(|a, b| a.1.cmp(&b.1).reverse())
+            .map(|(channel_id, percent)| Self {
+                name: channels.get(channel_id).cloned().unwrap_or_default().name,
+                percent,
+            })
+            .collect()
+    }
+}
+
+impl Report {
+    pub fn render(&self, format: Format) -> Result<String> {
+        match format {
+            Format::Text => Ok(text::render(self)),
+            Format::Json => Ok(serde_json::to_string_pretty(self)?),
+            Format::Csv => csv::render(self),
+            Format::Markdown => Ok(markdown::render(self)),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "markdown" | "md" => Ok(Self::Markdown),
+            _ => Err(Error::UnknownValue {
+                kind: "format",
+                value: s.to_string(),
+                expected: "text, json, csv or markdown",
+            }),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Text => "text",
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Markdown => "markdown",
+        };
+        write!(f, "{}", name)
+    }
+}
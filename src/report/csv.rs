@@ -0,0 +1,73 @@
+use ::csv::Writer;
+use itertools::Itertools;
+
+use super::{Report, Section};
+use crate::error::Result;
+
+/// One row per ranking entry, with the year and section in the first columns.
+/// Whole range sections leave the year empty, and so do trend rows, which use
+/// the period as name.
+pub fn render(report: &Report) -> Result<String> {
+    let mut writer = Writer::from_writer(Vec::new());
+    writer.write_record([
+        "year", "section", "rank", "name", "count", "channels", "note",
+    ])?;
+    for period in &report.periods {
+        let year = period.year.to_string();
+        for (name, count) in [("messages", period.messages), ("mentions", period.mentions)] {
+            let count = count.to_string();
+            writer.write_record([year.as_str(), "total", "", name, count.as_str(), "", ""])?;
+        }
+        for section in &period.sections {
+            write_section(&mut writer, &year, section)?;
+        }
+    }
+    for section in &report.sections {
+        write_section(&mut writer, "", section)?;
+    }
+    if let Some(trend) = &report.trend {
+        for row in &trend.rows {
+            for (name, count) in [
+                ("messages", row.messages),
+                ("mentions", row.mentions),
+                ("reactions", row.reactions),
+            ] {
+                let section = format!("trend {}", name);
+                let count = count.to_string();
+                writer.write_record([
+                    "",
+                    section.as_str(),
+                    "",
+                    row.period.as_str(),
+                    count.as_str(),
+                    "",
+                    "",
+                ])?;
+            }
+        }
+    }
+    let bytes = writer.into_inner().map_err(|why| why.into_error())?;
+    Ok(String::from_utf8(bytes).expect("CSV written from strings is UTF-8"))
+}
+
+fn write_section(writer: &mut Writer<Vec<u8>>, year: &str, section: &Section) -> Result<()> {
+    for entry in &section.entries {
+        let channels = entry
+            .channels
+            .iter()
+            .map(|channel| format!("{}: {}%", channel.name, channel.percent))
+            .join("; ");
+        let rank = entry.rank.map(|rank| rank.to_string()).unwrap_or_default();
+        let count = entry.count.to_string();
+        writer.write_record([
+            year,
+            section.title.as_str(),
+            rank.as_str(),
+            entry.name.as_str(),
+            count.as_str(),
+            channels.as_str(),
+            entry.note.as_deref().unwrap_or_default(),
+        ])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use std::fmt::Write;
+
+use itertools::Itertools;
+
+use super::{Entry, Report, Section};
+
+pub fn render(report: &Report) -> String {
+    let mut output = String::new();
+    for period in &report.periods {
+        writeln!(output, "## {}", period.year).unwrap();
+        writeln!(output).unwrap();
+        writeln!(output, "- Messages: {}", period.messages).unwrap();
+        writeln!(output, "- Mentions: {}", period.mentions).unwrap();
+        writeln!(output).unwrap();
+        for section in &period.sections {
+            write_section(&mut output, section);
+        }
+    }
+    if !report.sections.is_empty() {
+        writeln!(output, "## Whole range").unwrap();
+        writeln!(output).unwrap();
+        for section in &report.sections {
+            write_section(&mut output, section);
+        }
+    }
+    if let Some(trend) = &report.trend {
+        writeln!(output, "## Trend per {}", trend.granularity).unwrap();
+        writeln!(output).unwrap();
+        writeln!(output, "| Period | Messages | Mentions | Reactions |").unwrap();
+        writeln!(output, "| --- | ---: | ---: | ---: |").unwrap();
+        for row in &trend.rows {
+            writeln!(
+                output,
+                "| {} | {} | {} | {} |",
+                row.period, row.messages, row.mentions, row.reactions
+            )
+            .unwrap();
+        }
+        writeln!(output).unwrap();
+    }
+    output
+}
+
+fn write_section(output: &mut String, section: &Section) {
+    writeln!(output, "### {}", section.title).unwrap();
+    writeln!(output).unwrap();
+    writeln!(output, "| # | Name | Count | Details |").unwrap();
+    writeln!(output, "| ---: | --- | ---: | --- |").unwrap();
+    for entry in &section.entries {
+        writeln!(
+            output,
+            "| {} | {} | {} | {} |",
+            entry.rank.map(|rank| rank.to_string()).unwrap_or_default(),
+            escape(&entry.name),
+            entry.count,
+            escape(&details(entry)),
+        )
+        .unwrap();
+    }
+    writeln!(output).unwrap();
+}
+
+fn details(entry: &Entry) -> String {
+    entry
+        .channels
+        .iter()
+        .map(|channel| format!("{} {}%", channel.name, channel.percent))
+        .chain(entry.note.clone())
+        .join(", ")
+}
+
+fn escape(cell: &str) -> String {
+    cell.replace('|', "\\|").replace('\n', " ")
+}
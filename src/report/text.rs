@@ -0,0 +1,62 @@
+use std::fmt::Write;
+
+use super::{Entry, Report, Section};
+
+/// The plain layout calc has always printed.
+pub fn render(report: &Report) -> String {
+    let mut output = String::new();
+    for period in &report.periods {
+        writeln!(output).unwrap();
+        writeln!(output, "Year: {}", period.year).unwrap();
+        writeln!(output, "Messages: {}", period.messages).unwrap();
+        writeln!(output, "Mentions: {}", period.mentions).unwrap();
+        writeln!(output).unwrap();
+        for section in &period.sections {
+            write_section(&mut output, section);
+        }
+    }
+    if !report.sections.is_empty() {
+        writeln!(output).unwrap();
+        writeln!(output, "Whole range").unwrap();
+        writeln!(output).unwrap();
+        for section in &report.sections {
+            write_section(&mut output, section);
+        }
+    }
+    if let Some(trend) = &report.trend {
+        writeln!(output, "trend per {}", trend.granularity).unwrap();
+        for row in &trend.rows {
+            writeln!(
+                output,
+                "{}: messages {} mentions {} reactions {}",
+                row.period, row.messages, row.mentions, row.reactions
+            )
+            .unwrap();
+        }
+        writeln!(output).unwrap();
+    }
+    output
+}
+
+fn write_section(output: &mut String, section: &Section) {
+    writeln!(output, "{}", section.title).unwrap();
+    for entry in &section.entries {
+        writeln!(output, "{}", entry_line(entry)).unwrap();
+    }
+    writeln!(output).unwrap();
+}
+
+fn entry_line(entry: &Entry) -> String {
+    let mut line = String::new();
+    if let Some(rank) = entry.rank {
+        write!(line, "{} ", rank).unwrap();
+    }
+    write!(line, "{}: {}", entry.name, entry.count).unwrap();
+    for channel in &entry.channels {
+        write!(line, " {}: {}%", channel.name, channel.percent).unwrap();
+    }
+    if let Some(note) = &entry.note {
+        write!(line, " {}", note).unwrap();
+    }
+    line
+}
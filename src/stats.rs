@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serenity::all::{ChannelId, UserId};
+
+use crate::message_data::{Emoji, JsonData, MessageData, UserData};
+use crate::words::{count_words, WordCounter};
+
+pub type Counter<K> = HashMap<K, usize>;
+
+pub type ChannelCounter = Counter<ChannelId>;
+
+pub type CounterPerChannel<K> = HashMap<K, ChannelCounter>;
+
+pub type UserCounter = Counter<UserId>;
+pub type UserCounterPerChannel = CounterPerChannel<UserId>;
+
+pub type EmojiCounter = Counter<Emoji>;
+pub type EmojiCounterPerChannel = CounterPerChannel<Emoji>;
+pub type EmojiCounterPerUser = HashMap<UserId, EmojiCounter>;
+
+pub type EmojiLastUsed = HashMap<Emoji, DateTime<Utc>>;
+
+pub type WordCounterPerUser = HashMap<UserId, WordCounter>;
+pub type WordCounterPerChannel = HashMap<ChannelId, WordCounter>;
+
+/// Counts keyed by (replying user, replied user).
+pub type ReplyCounter = Counter<(UserId, UserId)>;
+
+/// Whether a message is counted: it must be written by a current member,
+/// who is not a bot unless `include_bots`, outside the excluded channels
+/// and their threads.
+pub fn is_counted(
+    data: &JsonData,
+    message: &MessageData,
+    exclude_channels: &[ChannelId],
+    include_bots: bool,
+) -> bool {
+    let channel_id = &message.channel_id;
+    let parent_id = data
+        .channels
+        .get(channel_id)
+        .and_then(|channel| channel.parent_id);
+    if exclude_channels.contains(channel_id)
+        || parent_id.is_some_and(|parent_id| exclude_channels.contains(&parent_id))
+    {
+        return false;
+    }
+    match data.members.get(&message.author_id) {
+        Some(user) => include_bots || !user.is_bot,
+        None => false,
+    }
+}
+
+pub fn calc_messages(
+    message: &MessageData,
+    user_message_sum: &mut UserCounter,
+    user_message_sum_per_channels: &mut UserCounterPerChannel,
+) {
+    let user_id = message.author_id;
+    let message_count = user_message_sum.entry(user_id).or_default();
+    let message_count_per_channel = user_message_sum_per_channels
+        .entry(user_id)
+        .or_default()
+        .entry(message.channel_id)
+        .or_default();
+
+    *message_count_per_channel += 1;
+    *message_count += 1;
+}
+
+pub fn calc_mention(
+    message: &MessageData,
+    user_mention_sum: &mut UserCounter,
+    user_mention_sum_per_channels: &mut UserCounterPerChannel,
+) {
+    message.mentions.iter().for_each(|mention| {
+        let mention_count = user_mention_sum.entry(*mention).or_default();
+        let mention_count_per_channel = user_mention_sum_per_channels
+            .entry(*mention)
+            .or_default()
+            .entry(message.channel_id)
+            .or_default();
+
+        *mention_count += 1;
+        *mention_count_per_channel += 1;
+    });
+}
+
+pub fn calc_emojis(
+    message: &MessageData,
+    emoji_sum: &mut EmojiCounter,
+    emoji_sum_per_channels: &mut EmojiCounterPerChannel,
+    reaction_sum: &mut UserCounter,
+) {
+    let reaction_counter = reaction_sum.entry(message.author_id).or_default();
+    // super reactions count as reactions, as they did before being stored apart
+    message
+        .reactions
+        .iter()
+        .chain(&message.burst_reactions)
+        .for_each(|(emoji, count)| {
+            let count = *count as usize;
+            let emoji_count = emoji_sum.entry(emoji.clone()).or_default();
+            let emoji_count_per_channel = emoji_sum_per_channels
+                .entry(emoji.clone())
+                .or_default()
+                .entry(message.channel_id)
+                .or_default();
+
+            *emoji_count += count;
+            *emoji_count_per_channel += count;
+            *reaction_counter += count;
+        });
+}
+
+pub fn calc_used_emojis(
+    message: &MessageData,
+    used_emoji_sum: &mut EmojiCounter,
+    used_emoji_sum_per_channels: &mut EmojiCounterPerChannel,
+) {
+    message.used_emojis.iter().for_each(|(emoji, count)| {
+        let emoji_count = used_emoji_sum.entry(emoji.clone()).or_default();
+        let emoji_count_per_channel = used_emoji_sum_per_channels
+            .entry(emoji.clone())
+            .or_default()
+            .entry(message.channel_id)
+            .or_default();
+
+        *emoji_count += count;
+        *emoji_count_per_channel += count;
+    });
+}
+
+/// Records the send time of the newest message each emoji was typed in or
+/// reacted to. Reactions carry no time, so their message's time is used.
+pub fn calc_emoji_last_used(message: &MessageData, emoji_last_used: &mut EmojiLastUsed) {
+    message
+        .reactions
+        .keys()
+        .chain(message.burst_reactions.keys())
+        .chain(message.used_emojis.keys())
+        .for_each(|emoji| {
+            let last_used = emoji_last_used
+                .entry(emoji.clone())
+                .or_insert(message.send_time);
+            *last_used = (*last_used).max(message.send_time);
+        });
+}
+
+pub fn calc_reactors(
+    message: &MessageData,
+    members: &HashMap<UserId, UserData>,
+    include_bots: bool,
+    reaction_given_sum: &mut UserCounter,
+    user_emoji_sum: &mut EmojiCounterPerUser,
+) {
+    // super reactions count as reactions given, as they count as reactions received
+    message
+        .reactors
+        .iter()
+        .chain(&message.burst_reactors)
+        .for_each(|(emoji, users)| {
+            users.iter().for_each(|user_id| {
+                if !include_bots && members.get(user_id).is_some_and(|user| user.is_bot) {
+                    return;
+                }
+                let reaction_count = reaction_given_sum.entry(*user_id).or_default();
+                let emoji_count = user_emoji_sum
+                    .entry(*user_id)
+                    .or_default()
+                    .entry(emoji.clone())
+                    .or_default();
+
+                *reaction_count += 1;
+                *emoji_count += 1;
+            });
+        });
+}
+
+pub fn calc_replies(
+    message: &MessageData,
+    members: &HashMap<UserId, UserData>,
+    include_bots: bool,
+    reply_sum: &mut ReplyCounter,
+) {
+    let replied_id = match message.replied_user() {
+        Some(replied_id) => replied_id,
+        None => return,
+    };
+    if replied_id == message.author_id {
+        return;
+    }
+    if !include_bots && members.get(&replied_id).is_some_and(|user| user.is_bot) {
+        return;
+    }
+    *reply_sum
+        .entry((message.author_id, replied_id))
+        .or_default() += 1;
+}
+
+/// Counts the words of messages whose content is stored.
+pub fn calc_words(
+    message: &MessageData,
+    user_word_sum: &mut WordCounterPerUser,
+    channel_word_sum: &mut WordCounterPerChannel,
+) {
+    let content = match &message.content {
+        Some(content) => content,
+        None => return,
+    };
+    let mut words = WordCounter::new();
+    count_words(content, &mut words);
+    for (word, count) in words {
+        *user_word_sum
+            .entry(message.author_id)
+            .or_default()
+            .entry(word.clone())
+            .or_default() += count;
+        *channel_word_sum
+            .entry(message.channel_id)
+            .or_default()
+            .entry(word)
+            .or_default() += count;
+    }
+}
+
+/// Returns the user each user replied to most, with the reply count.
+pub fn reply_partners(reply_sum: &ReplyCounter) -> HashMap<UserId, (UserId, usize)> {
+    let mut partners = HashMap::<UserId, (UserId, usize)>::new();
+    for ((user_id, replied_id), count) in reply_sum {
+        let partner = partners.entry(*user_id).or_insert((*replied_id, *count));
+        if *count > partner.1 || (*count == partner.1 && *replied_id < partner.0) {
+            *partner = (*replied_id, *count);
+        }
+    }
+    partners
+}
+
+/// Returns the emoji used most, with its count. The smallest key wins ties,
+/// so the favorite does not depend on hash order.
+pub fn favorite_emoji(counter: &EmojiCounter) -> Option<(&Emoji, usize)> {
+    counter
+        .iter()
+        .max_by(|(a, a_count), (b, b_count)| {
+            a_count.cmp(b_count).then_with(|| b.key().cmp(&a.key()))
+        })
+        .map(|(emoji, count)| (emoji, *count))
+}
+
+pub fn extract_top<K: Eq + Hash>(counter: Counter<K>, top: usize) -> Vec<(K, usize)> {
+    counter
+        .into_iter()
+        .sorted_by(|a, b| a.1.cmp(&b.1).reverse())
+        .take(top)
+        .collect()
+}
+
+/// Every counter of one reporting period.
+#[derive(Default, Debug, Clone)]
+pub struct Stats {
+    pub message_sum: usize,
+    pub mention_sum: usize,
+    pub user_message_sum: UserCounter,
+    pub user_message_sum_per_channels: UserCounterPerChannel,
+    pub user_mention_sum: UserCounter,
+    pub user_mention_sum_per_channels: UserCounterPerChannel,
+    pub emoji_sum: EmojiCounter,
+    pub emoji_sum_per_channels: EmojiCounterPerChannel,
+    pub used_emoji_sum: EmojiCounter,
+    pub used_emoji_sum_per_channels: EmojiCounterPerChannel,
+    pub emoji_last_used: EmojiLastUsed,
+    pub reaction_sum: UserCounter,
+    pub reaction_given_sum: UserCounter,
+    pub user_emoji_sum: EmojiCounterPerUser,
+    pub reply_sum: ReplyCounter,
+    pub user_word_sum: WordCounterPerUser,
+    pub channel_word_sum: WordCounterPerChannel,
+}
+
+impl Stats {
+    pub fn add(
+        &mut self,
+        message: &MessageData,
+        members: &HashMap<UserId, UserData>,
+        include_bots: bool,
+    ) {
+        self.message_sum += 1;
+        self.mention_sum += message.mentions.len();
+
+        calc_messages(
+            message,
+            &mut self.user_message_sum,
+            &mut self.user_message_sum_per_channels,
+        );
+
+        calc_mention(
+            message,
+            &mut self.user_mention_sum,
+            &mut self.user_mention_sum_per_channels,
+        );
+
+        calc_emojis(
+            message,
+            &mut self.emoji_sum,
+            &mut self.emoji_sum_per_channels,
+            &mut self.reaction_sum,
+        );
+
+        calc_used_emojis(
+            message,
+            &mut self.used_emoji_sum,
+            &mut self.used_emoji_sum_per_channels,
+        );
+
+        calc_emoji_last_used(message, &mut self.emoji_last_used);
+
+        calc_reactors(
+            message,
+            members,
+            include_bots,
+            &mut self.reaction_given_sum,
+            &mut self.user_emoji_sum,
+        );
+
+        calc_replies(message, members, include_bots, &mut self.reply_sum);
+
+        calc_words(message, &mut self.user_word_sum, &mut self.channel_word_sum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::{ChannelType, GuildId};
+
+    use super::*;
+    use crate::message_data::ChannelData;
+
+    const ALICE: u64 = 222222222222222222;
+    const BOT: u64 = 333333333333333333;
+    const FORMER_MEMBER: u64 = 444444444444444444;
+    const CHANNEL: u64 = 666666666666666666;
+    const THREAD: u64 = 777777777777777777;
+
+    #[test]
+    fn favorite_emoji_ties_go_to_the_smallest_key() {
+        let counter = EmojiCounter::from([
+            (Emoji::Unicode("🙂".into()), 2),
+            (Emoji::Unicode("👍".into()), 2),
+            (Emoji::Unicode("🎉".into()), 1),
+        ]);
+        assert_eq!(
+            favorite_emoji(&counter),
+            Some((&Emoji::Unicode("👍".into()), 2))
+        );
+        assert_eq!(favorite_emoji(&EmojiCounter::new()), None);
+    }
+
+    #[test]
+    fn messages_of_current_members_outside_excluded_channels_are_counted() {
+        let members = [(ALICE, false), (BOT, true)]
+            .into_iter()
+            .map(|(id, is_bot)| {
+                let user_id = UserId::new(id);
+                let user = UserData::new(user_id, "user".into(), "user".into(), None, is_bot);
+                (user_id, user)
+            })
+            .collect();
+        let thread = ChannelData::new(
+            ChannelId::new(THREAD),
+            "thread".into(),
+            ChannelType::PublicThread,
+            vec![],
+            Some(ChannelId::new(CHANNEL)),
+        );
+        let data = JsonData::new(
+            GuildId::new(1),
+            members,
+            HashMap::from([(thread.channel_id, thread)]),
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let counted = |channel_id: u64, author_id: u64, exclude: &[ChannelId], bots: bool| {
+            let message = MessageData::for_test(channel_id, 1, author_id);
+            is_counted(&data, &message, exclude, bots)
+        };
+
+        assert!(counted(THREAD, ALICE, &[], false));
+        assert!(!counted(THREAD, FORMER_MEMBER, &[], true));
+        assert!(!counted(THREAD, BOT, &[], false));
+        assert!(counted(THREAD, BOT, &[], true));
+        // threads follow the channel they were created in
+        assert!(!counted(THREAD, ALICE, &[ChannelId::new(CHANNEL)], false));
+        assert!(!counted(CHANNEL, ALICE, &[ChannelId::new(CHANNEL)], false));
+    }
+}
@@ -0,0 +1,508 @@
+mod sharded;
+mod sqlite;
+
+use std::collections::HashMap;
+use std::env;
+use std::mem;
+use std::path::PathBuf;
+
+use serenity::all::{ChannelId, GuildId, MessageId, UserId};
+
+use crate::error::{Error, Result};
+use crate::forget::{forget_users, forget_users_in_message, ForgetMode};
+use crate::message_data::{JsonData, MessageData};
+use crate::utils::{filename, shard_dirname, sqlite_filename};
+
+pub use sharded::{MessageReader, ShardedStore};
+pub use sqlite::SqliteStore;
+
+/// A change to the stored messages, so that changes can be applied together.
+pub enum MessageChange {
+    /// Stores a message, replacing the stored one with the same id.
+    Upsert(MessageData),
+    /// Replaces a stored message with its edited version, keeping who
+    /// reacted as edits do not say, or stores it if it is missing.
+    Edit(MessageData),
+    /// Applies a function to a stored message, if it is stored.
+    Update {
+        channel_id: ChannelId,
+        message_id: MessageId,
+        f: Box<dyn FnOnce(&mut MessageData) + Send>,
+    },
+    Delete {
+        channel_id: ChannelId,
+        message_id: MessageId,
+    },
+}
+
+/// Where a guild archive is kept, selected with the `STORAGE` environment variable.
+pub enum Storage {
+    Json(PathBuf),
+    Sqlite(SqliteStore),
+    Sharded(ShardedStore),
+}
+
+impl Storage {
+    pub fn from_env(guild_id: GuildId) -> Result<Self> {
+        match env::var("STORAGE").as_deref() {
+            Ok("json") | Err(_) => Ok(Self::Json(filename(guild_id).into())),
+            Ok("sqlite") => Ok(Self::Sqlite(SqliteStore::open(
+                sqlite_filename(guild_id),
+                guild_id,
+            )?)),
+            Ok("sharded") => Ok(Self::Sharded(ShardedStore::open(
+                shard_dirname(guild_id),
+                guild_id,
+            )?)),
+            Ok(other) => Err(Error::InvalidEnv {
+                name: "STORAGE",
+                value: other.to_string(),
+            }),
+        }
+    }
+
+    pub fn load(&self) -> Result<JsonData> {
+        match self {
+            Self::Json(path) => JsonData::load(path),
+            Self::Sqlite(store) => store.load(),
+            Self::Sharded(store) => store.load(),
+        }
+    }
+
+    /// Loads members, channels and emojis without the messages.
+    pub fn load_metadata(&self) -> Result<JsonData> {
+        match self {
+            Self::Json(path) => JsonData::load_metadata(path),
+            Self::Sqlite(store) => store.load_metadata(),
+            Self::Sharded(store) => store.load_metadata(),
+        }
+    }
+
+    /// Loads the metadata and calls `f` with it and every stored message. The
+    /// JSON archive is parsed once for both, while the sharded and SQLite
+    /// backends read messages one at a time, so the whole archive is never in memory.
+    pub fn for_each_message(&self, mut f: impl FnMut(&JsonData, MessageData)) -> Result<JsonData> {
+        match self {
+            Self::Json(path) => {
+                let mut data = JsonData::load(path)?;
+                let messages = mem::take(&mut data.messages);
+                for message in messages.into_values().flatten() {
+                    f(&data, message);
+                }
+                Ok(data)
+            }
+            Self::Sqlite(store) => {
+                let data = store.load_metadata()?;
+                store.for_each_message(|message| f(&data, message))?;
+                Ok(data)
+            }
+            Self::Sharded(store) => {
+                let data = store.load_metadata()?;
+                for message in store.messages()? {
+                    f(&data, message?);
+                }
+                Ok(data)
+            }
+        }
+    }
+
+    /// Newest message of each channel the getter fetched, none before the first crawl.
+    pub fn fetched_until(&self) -> Result<HashMap<ChannelId, MessageId>> {
+        match self {
+            Self::Json(path) if !path.exists() => Ok(HashMap::new()),
+            Self::Json(path) => Ok(JsonData::load_metadata(path)?.fetched_until),
+            Self::Sqlite(store) => Ok(store.load_metadata()?.fetched_until),
+            Self::Sharded(store) => Ok(store.load_metadata()?.fetched_until),
+        }
+    }
+
+    /// Replaces the whole archive. Users forgotten in the stored archive stay forgotten.
+    pub fn save(&mut self, mut data: JsonData) -> Result<()> {
+        forget_users(&mut data, &self.forgotten_users()?);
+        match self {
+            Self::Json(path) => data.save(path),
+            Self::Sqlite(store) => store.save(&data),
+            Self::Sharded(store) => store.save(&data),
+        }
+    }
+
+    /// Lets `f` replace members, channels and emojis, keeping the stored
+    /// messages. The JSON archive is loaded once for both.
+    pub fn update_metadata(&mut self, f: impl FnOnce(&mut JsonData)) -> Result<()> {
+        let mut data = match self {
+            Self::Json(path) => JsonData::load(&*path)?,
+            Self::Sqlite(store) => store.load_metadata()?,
+            Self::Sharded(store) => store.load_metadata()?,
+        };
+        let messages = mem::take(&mut data.messages);
+        f(&mut data);
+        // the members may list forgotten users again
+        let forgotten = data.forgotten_users.clone();
+        forget_users(&mut data, &forgotten);
+        match self {
+            Self::Json(path) => {
+                data.messages = messages;
+                data.save(path)
+            }
+            Self::Sqlite(store) => store.save_metadata(&data),
+            Self::Sharded(store) => store.save_metadata(&data),
+        }
+    }
+
+    /// Replaces the metadata and adds the messages that are not stored yet.
+    /// Stored channels that are no longer listed, such as old archived
+    /// threads, are kept. Forgotten users are forgotten again before storing.
+    pub fn merge(&mut self, mut data: JsonData) -> Result<()> {
+        match self {
+            Self::Json(path) if !path.exists() => data.save(path),
+            Self::Json(path) => {
+                let mut stored = JsonData::load(&*path)?;
+                forget_users(&mut data, &stored.forgotten_users);
+                stored.members = data.members;
+                stored.channels.extend(data.channels);
+                stored.emojis = data.emojis;
+                stored.fetched_until = data.fetched_until;
+                for (channel_id, messages) in data.messages {
+                    stored.merge_messages(channel_id, messages);
+                }
+                stored.save(path)
+            }
+            Self::Sqlite(store) => {
+                keep_stored_metadata(&mut data, store.load_metadata()?);
+                store.merge(&data)
+            }
+            Self::Sharded(store) => {
+                keep_stored_metadata(&mut data, store.load_metadata()?);
+                store.merge(&data)
+            }
+        }
+    }
+
+    /// Users deleted with the forget binary, which must not come back.
+    fn forgotten_users(&self) -> Result<HashMap<UserId, ForgetMode>> {
+        match self {
+            Self::Json(path) if !path.exists() => Ok(HashMap::new()),
+            Self::Json(path) => Ok(JsonData::load_metadata(path)?.forgotten_users),
+            Self::Sqlite(store) => store.forgotten_users(),
+            Self::Sharded(store) => Ok(store.load_metadata()?.forgotten_users),
+        }
+    }
+
+    /// Applies `f` to a stored message, returning whether the message was found.
+    pub fn update_message(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        f: impl FnOnce(&mut MessageData),
+    ) -> Result<bool> {
+        match self {
+            Self::Json(path) => {
+                let mut data = JsonData::load(&*path)?;
+                let forgotten = data.forgotten_users.clone();
+                match data.message_mut(channel_id, message_id) {
+                    Some(message) => {
+                        f(message);
+                        forget_users_in_message(message, &forgotten);
+                    }
+                    None => return Ok(false),
+                }
+                data.save(path)?;
+                Ok(true)
+            }
+            Self::Sqlite(store) => {
+                let forgotten = store.forgotten_users()?;
+                store.update_message(message_id, |message| {
+                    f(message);
+                    forget_users_in_message(message, &forgotten);
+                })
+            }
+            Self::Sharded(store) => {
+                let forgotten = store.load_metadata()?.forgotten_users;
+                store.update_message(channel_id, message_id, |message| {
+                    f(message);
+                    forget_users_in_message(message, &forgotten);
+                })
+            }
+        }
+    }
+
+    /// Stores a message, unless a user forgotten with [`ForgetMode::Remove`] wrote it.
+    pub fn upsert_message(&mut self, mut message: MessageData) -> Result<()> {
+        match self {
+            Self::Json(path) => {
+                let mut data = JsonData::load(&*path)?;
+                if forget_users_in_message(&mut message, &data.forgotten_users) {
+                    data.upsert_message(message);
+                    data.save(path)?;
+                }
+                Ok(())
+            }
+            Self::Sqlite(store) => {
+                if forget_users_in_message(&mut message, &store.forgotten_users()?) {
+                    store.upsert_message(&message)?;
+                }
+                Ok(())
+            }
+            Self::Sharded(store) => {
+                let forgotten = store.load_metadata()?.forgotten_users;
+                if forget_users_in_message(&mut message, &forgotten) {
+                    store.upsert_message(&message)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes a message, returning whether it was stored.
+    pub fn delete_message(&mut self, channel_id: ChannelId, message_id: MessageId) -> Result<bool> {
+        match self {
+            Self::Json(path) => {
+                let mut data = JsonData::load(&*path)?;
+                if data.remove_message(channel_id, message_id).is_none() {
+                    return Ok(false);
+                }
+                data.save(path)?;
+                Ok(true)
+            }
+            Self::Sqlite(store) => store.delete_message(message_id),
+            Self::Sharded(store) => store.delete_message(channel_id, message_id),
+        }
+    }
+
+    /// Applies changes in order. The JSON archive is loaded and saved once
+    /// for all of them, the other backends apply them one at a time.
+    pub fn apply(&mut self, changes: Vec<MessageChange>) -> Result<()> {
+        let path = match self {
+            Self::Json(path) => path,
+            _ => {
+                for change in changes {
+                    self.apply_one(change)?;
+                }
+                return Ok(());
+            }
+        };
+        let mut data = JsonData::load(&*path)?;
+        let forgotten = data.forgotten_users.clone();
+        for change in changes {
+            match change {
+                MessageChange::Upsert(mut message) => {
+                    if forget_users_in_message(&mut message, &forgotten) {
+                        data.upsert_message(message);
+                    }
+                }
+                MessageChange::Edit(mut message) => {
+                    match data.message_mut(message.channel_id, message.message_id) {
+                        Some(stored) => {
+                            message.reactors = mem::take(&mut stored.reactors);
+                            message.burst_reactors = mem::take(&mut stored.burst_reactors);
+                            *stored = message;
+                            forget_users_in_message(stored, &forgotten);
+                        }
+                        None => {
+                            if forget_users_in_message(&mut message, &forgotten) {
+                                data.upsert_message(message);
+                            }
+                        }
+                    }
+                }
+                MessageChange::Update {
+                    channel_id,
+                    message_id,
+                    f,
+                } => {
+                    if let Some(message) = data.message_mut(channel_id, message_id) {
+                        f(message);
+                        forget_users_in_message(message, &forgotten);
+                    }
+                }
+                MessageChange::Delete {
+                    channel_id,
+                    message_id,
+                } => {
+                    data.remove_message(channel_id, message_id);
+                }
+            }
+        }
+        data.save(path)
+    }
+
+    fn apply_one(&mut self, change: MessageChange) -> Result<()> {
+        match change {
+            MessageChange::Upsert(message) => self.upsert_message(message),
+            MessageChange::Edit(message) => {
+                let edited = message.clone();
+                let updated =
+                    self.update_message(message.channel_id, message.message_id, |stored| {
+                        let reactors = mem::take(&mut stored.reactors);
+                        let burst_reactors = mem::take(&mut stored.burst_reactors);
+                        *stored = edited;
+                        stored.reactors = reactors;
+                        stored.burst_reactors = burst_reactors;
+                    })?;
+                if !updated {
+                    self.upsert_message(message)?;
+                }
+                Ok(())
+            }
+            MessageChange::Update {
+                channel_id,
+                message_id,
+                f,
+            } => self.update_message(channel_id, message_id, f).map(drop),
+            MessageChange::Delete {
+                channel_id,
+                message_id,
+            } => self.delete_message(channel_id, message_id).map(drop),
+        }
+    }
+}
+
+/// Keeps what merging must not lose from the stored metadata: channels that
+/// are no longer listed, and the forgotten users.
+fn keep_stored_metadata(data: &mut JsonData, stored: JsonData) {
+    forget_users(data, &stored.forgotten_users);
+    for (channel_id, channel) in stored.channels {
+        data.channels.entry(channel_id).or_insert(channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process;
+
+    use serenity::all::ChannelType;
+
+    use super::*;
+    use crate::forget::forget_user;
+    use crate::message_data::{ChannelData, Emoji};
+
+    const GUILD: u64 = 111111111111111111;
+    const ALICE: u64 = 222222222222222222;
+    const BOB: u64 = 333333333333333333;
+    const CHANNEL: u64 = 666666666666666666;
+
+    fn sqlite() -> Storage {
+        Storage::Sqlite(SqliteStore::open(":memory:", GuildId::new(GUILD)).unwrap())
+    }
+
+    fn archive(messages: Vec<MessageData>) -> JsonData {
+        JsonData::new(
+            GuildId::new(GUILD),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::from([(ChannelId::new(CHANNEL), messages)]),
+        )
+    }
+
+    #[test]
+    fn removed_users_stay_forgotten() {
+        let mut storage = sqlite();
+        let messages = vec![
+            MessageData::for_test(CHANNEL, 2, ALICE),
+            MessageData::for_test(CHANNEL, 1, BOB),
+        ];
+        let mut data = archive(messages.clone());
+        forget_user(&mut data, UserId::new(ALICE), ForgetMode::Remove);
+        storage.save(data).unwrap();
+        // the getter fetches the removed messages again
+        storage.merge(archive(messages.clone())).unwrap();
+        storage
+            .upsert_message(MessageData::for_test(CHANNEL, 3, ALICE))
+            .unwrap();
+        assert_eq!(
+            storage.load().unwrap().messages[&ChannelId::new(CHANNEL)].len(),
+            1
+        );
+
+        // a full crawl starts from an archive without the forgotten users
+        storage.save(archive(vec![])).unwrap();
+        storage.merge(archive(messages)).unwrap();
+
+        let stored = storage.load().unwrap();
+        assert_eq!(stored.messages[&ChannelId::new(CHANNEL)].len(), 1);
+        assert_eq!(
+            stored.forgotten_users[&UserId::new(ALICE)],
+            ForgetMode::Remove
+        );
+    }
+
+    #[test]
+    fn recorded_messages_leave_the_getter_position() {
+        let mut storage = sqlite();
+        let mut data = archive(vec![MessageData::for_test(CHANNEL, 1, BOB)]);
+        data.fetched_until
+            .insert(ChannelId::new(CHANNEL), MessageId::new(1));
+        storage.merge(data).unwrap();
+        storage
+            .upsert_message(MessageData::for_test(CHANNEL, 5, BOB))
+            .unwrap();
+
+        let fetched_until = storage.fetched_until().unwrap();
+        assert_eq!(fetched_until[&ChannelId::new(CHANNEL)], MessageId::new(1));
+    }
+
+    #[test]
+    fn merging_keeps_unlisted_channels() {
+        let dir = env::temp_dir().join(format!("sharded-{}", process::id()));
+        let sharded = ShardedStore::open(&dir, GuildId::new(GUILD)).unwrap();
+        for mut storage in [sqlite(), Storage::Sharded(sharded)] {
+            let mut data = archive(vec![]);
+            let thread = ChannelData::new(
+                ChannelId::new(CHANNEL),
+                "archived thread".into(),
+                ChannelType::PublicThread,
+                vec![],
+                None,
+            );
+            data.channels.insert(thread.channel_id, thread);
+            storage.merge(data).unwrap();
+            // archived threads of private channels are not listed again
+            storage.merge(archive(vec![])).unwrap();
+
+            let channels = storage.load_metadata().unwrap().channels;
+            assert!(channels.contains_key(&ChannelId::new(CHANNEL)));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn changes_apply_alike_on_every_backend() {
+        let path = env::temp_dir().join(format!("changes-{}.json", process::id()));
+        archive(vec![]).save(&path).unwrap();
+        for mut storage in [sqlite(), Storage::Json(path.clone())] {
+            storage.save(archive(vec![])).unwrap();
+            let thumbs_up = Emoji::Unicode("👍".into());
+            let mut edited = MessageData::for_test(CHANNEL, 1, ALICE);
+            edited.content = Some("edited".into());
+            storage
+                .apply(vec![
+                    MessageChange::Upsert(MessageData::for_test(CHANNEL, 1, ALICE)),
+                    MessageChange::Upsert(MessageData::for_test(CHANNEL, 2, BOB)),
+                    MessageChange::Update {
+                        channel_id: ChannelId::new(CHANNEL),
+                        message_id: MessageId::new(1),
+                        f: Box::new(move |message| {
+                            message.add_reaction(thumbs_up.clone(), Some(UserId::new(BOB)), false);
+                            message.add_reaction(thumbs_up, Some(UserId::new(ALICE)), true);
+                        }),
+                    },
+                    // edits do not say who reacted
+                    MessageChange::Edit(edited),
+                    MessageChange::Delete {
+                        channel_id: ChannelId::new(CHANNEL),
+                        message_id: MessageId::new(2),
+                    },
+                ])
+                .unwrap();
+
+            let messages = &storage.load().unwrap().messages[&ChannelId::new(CHANNEL)];
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].content.as_deref(), Some("edited"));
+            assert_eq!(messages[0].reactors.values().flatten().count(), 1);
+            assert_eq!(messages[0].burst_reactors.values().flatten().count(), 1);
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
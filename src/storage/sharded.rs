@@ -0,0 +1,448 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::vec::IntoIter;
+
+use serde::Serialize;
+use serde_json::Value;
+use serenity::all::{ChannelId, GuildId, MessageId};
+
+use crate::error::{Error, Result};
+use crate::message_data::migration::{self, FETCHED_UNTIL_VERSION, SCHEMA_VERSION};
+use crate::message_data::{JsonData, MessageData};
+
+const METADATA_FILE: &str = "meta.json";
+const MESSAGES_DIR: &str = "messages";
+/// Where upgraded shards are written before they replace `messages`.
+const UPGRADE_DIR: &str = "messages.upgrade";
+
+/// Archive split into `meta.json` and one NDJSON file of messages per channel,
+/// so messages can be read one at a time instead of loading the whole guild.
+pub struct ShardedStore {
+    guild_id: GuildId,
+    dir: PathBuf,
+}
+
+impl ShardedStore {
+    pub fn open(dir: impl Into<PathBuf>, guild_id: GuildId) -> Result<Self> {
+        let mut store = Self {
+            guild_id,
+            dir: dir.into(),
+        };
+        // an interrupted upgrade may have removed the shards, so it is finished first
+        store.upgrade()?;
+        let messages_dir = store.messages_dir();
+        fs::create_dir_all(&messages_dir).map_err(Error::file(&messages_dir))?;
+        // start empty like a new SQLite database, so the metadata can always be read
+        if !store.metadata_path().exists() {
+            store.save_metadata(&JsonData::new(
+                guild_id,
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+            ))?;
+        }
+        Ok(store)
+    }
+
+    pub fn load(&self) -> Result<JsonData> {
+        let mut data = self.load_metadata()?;
+        for message in self.messages()? {
+            let message = message?;
+            data.messages
+                .entry(message.channel_id)
+                .or_default()
+                .push(message);
+        }
+        Ok(data)
+    }
+
+    /// Reads `meta.json`, which [`ShardedStore::open`] upgraded, failing if a
+    /// newer build rewrote it since.
+    pub fn load_metadata(&self) -> Result<JsonData> {
+        let path = self.metadata_path();
+        let file = File::open(&path).map_err(Error::file(&path))?;
+        let value = serde_json::from_reader(BufReader::new(file))?;
+        Ok(serde_json::from_value(migration::migrate(value)?)?)
+    }
+
+    /// Streams the messages of every channel.
+    pub fn messages(&self) -> Result<MessageReader> {
+        Ok(MessageReader::new(self.channel_paths()?))
+    }
+
+    pub fn channel_messages(&self, channel_id: ChannelId) -> MessageReader {
+        let path = self.channel_path(channel_id);
+        let paths = if path.exists() { vec![path] } else { vec![] };
+        MessageReader::new(paths)
+    }
+
+    pub fn save(&mut self, data: &JsonData) -> Result<()> {
+        self.save_metadata(data)?;
+        for path in self.channel_paths()? {
+            fs::remove_file(&path).map_err(Error::file(&path))?;
+        }
+        for (channel_id, messages) in &data.messages {
+            let path = self.channel_path(*channel_id);
+            let mut writer = BufWriter::new(File::create(&path).map_err(Error::file(&path))?);
+            for message in messages {
+                write_line(&mut writer, message)?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn save_metadata(&mut self, data: &JsonData) -> Result<()> {
+        let mut metadata = JsonData::new(
+            self.guild_id,
+            data.members.clone(),
+            data.channels.clone(),
+            data.emojis.clone(),
+            HashMap::new(),
+        );
+        metadata.fetched_until = data.fetched_until.clone();
+        metadata.forgotten_users = data.forgotten_users.clone();
+        write_json(&self.metadata_path(), &metadata)
+    }
+
+    /// Replaces the metadata and appends messages that are not stored yet.
+    pub fn merge(&mut self, data: &JsonData) -> Result<()> {
+        self.save_metadata(data)?;
+        for (channel_id, messages) in &data.messages {
+            let mut known = HashSet::<MessageId>::new();
+            for message in self.channel_messages(*channel_id) {
+                known.insert(message?.message_id);
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.channel_path(*channel_id))?;
+            let mut writer = BufWriter::new(file);
+            for message in messages {
+                if known.insert(message.message_id) {
+                    write_line(&mut writer, message)?;
+                }
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn update_message(
+        &mut self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        f: impl FnOnce(&mut MessageData),
+    ) -> Result<bool> {
+        self.rewrite_channel(channel_id, |messages| {
+            match messages
+                .iter_mut()
+                .find(|message| message.message_id == message_id)
+            {
+                Some(message) => {
+                    f(message);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    pub fn upsert_message(&mut self, message: &MessageData) -> Result<()> {
+        let replaced = self.rewrite_channel(message.channel_id, |messages| {
+            match messages
+                .iter_mut()
+                .find(|stored| stored.message_id == message.message_id)
+            {
+                Some(stored) => {
+                    *stored = message.clone();
+                    true
+                }
+                None => false,
+            }
+        })?;
+        if !replaced {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.channel_path(message.channel_id))?;
+            let mut writer = BufWriter::new(file);
+            write_line(&mut writer, message)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_message(&mut self, channel_id: ChannelId, message_id: MessageId) -> Result<bool> {
+        self.rewrite_channel(channel_id, |messages| {
+            let length = messages.len();
+            messages.retain(|message| message.message_id != message_id);
+            messages.len() != length
+        })
+    }
+
+    /// Loads one channel, lets `f` edit it and writes it back if `f` returns true.
+    fn rewrite_channel(
+        &self,
+        channel_id: ChannelId,
+        f: impl FnOnce(&mut Vec<MessageData>) -> bool,
+    ) -> Result<bool> {
+        let mut messages = self
+            .channel_messages(channel_id)
+            .collect::<Result<Vec<_>>>()?;
+        if !f(&mut messages) {
+            return Ok(false);
+        }
+        let path = self.channel_path(channel_id);
+        let rewritten = path.with_extension("ndjson.tmp");
+        let mut writer = BufWriter::new(File::create(&rewritten).map_err(Error::file(&rewritten))?);
+        for message in &messages {
+            write_line(&mut writer, message)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(rewritten, &path).map_err(Error::file(&path))?;
+        Ok(true)
+    }
+
+    fn metadata_path(&self) -> PathBuf {
+        self.dir.join(METADATA_FILE)
+    }
+
+    fn messages_dir(&self) -> PathBuf {
+        self.dir.join(MESSAGES_DIR)
+    }
+
+    fn channel_path(&self, channel_id: ChannelId) -> PathBuf {
+        self.messages_dir().join(format!("{}.ndjson", channel_id))
+    }
+
+    fn channel_paths(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::<PathBuf>::new();
+        let messages_dir = self.messages_dir();
+        for entry in fs::read_dir(&messages_dir).map_err(Error::file(&messages_dir))? {
+            let path = entry.map_err(Error::file(&messages_dir))?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "ndjson")
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Rewrites every shard if the archive was written with an older schema
+    /// version. The upgraded shards are written apart and swapped in once the
+    /// upgraded `meta.json` is written, so an interrupted upgrade is either
+    /// redone or finished on the next open, and never applied twice.
+    fn upgrade(&self) -> Result<()> {
+        let path = self.metadata_path();
+        if !path.exists() {
+            return Ok(());
+        }
+        let file = File::open(&path).map_err(Error::file(&path))?;
+        let value: Value = serde_json::from_reader(BufReader::new(file))?;
+        let version = migration::version(&value)?;
+        let staging = self.dir.join(UPGRADE_DIR);
+        if version == SCHEMA_VERSION {
+            // an upgrade was interrupted after writing meta.json
+            if staging.exists() {
+                self.swap_in(&staging)?;
+            }
+            return Ok(());
+        }
+        // left by an upgrade interrupted before writing meta.json
+        if staging.exists() {
+            fs::remove_dir_all(&staging).map_err(Error::file(&staging))?;
+        }
+        fs::create_dir_all(&staging).map_err(Error::file(&staging))?;
+        let mut latest = HashMap::<ChannelId, MessageId>::new();
+        for shard in self.channel_paths()? {
+            let upgraded = staging.join(shard.file_name().unwrap_or_default());
+            let file = File::create(&upgraded).map_err(Error::file(&upgraded))?;
+            let mut writer = BufWriter::new(file);
+            let file = File::open(&shard).map_err(Error::file(&shard))?;
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(Error::file(&shard))?;
+                if line.is_empty() {
+                    continue;
+                }
+                let message = migration::migrate_message(serde_json::from_str(&line)?, version)?;
+                let message: MessageData = serde_json::from_value(message)?;
+                let message_id = latest
+                    .entry(message.channel_id)
+                    .or_insert(message.message_id);
+                *message_id = (*message_id).max(message.message_id);
+                write_line(&mut writer, &message)?;
+            }
+            writer.flush().map_err(Error::file(&upgraded))?;
+        }
+        let mut value = migration::migrate(value)?;
+        if version < FETCHED_UNTIL_VERSION {
+            // meta.json holds no messages, so the migration left this empty
+            value["fetched_until"] = serde_json::to_value(latest)?;
+        }
+        write_json(&path, &value)?;
+        self.swap_in(&staging)
+    }
+
+    /// Replaces the shards with the upgraded ones in `staging`.
+    fn swap_in(&self, staging: &Path) -> Result<()> {
+        let messages_dir = self.messages_dir();
+        if messages_dir.exists() {
+            fs::remove_dir_all(&messages_dir).map_err(Error::file(&messages_dir))?;
+        }
+        fs::rename(staging, &messages_dir).map_err(Error::file(&messages_dir))
+    }
+}
+
+/// Iterator over the messages of a list of NDJSON shards, reading one line at a time.
+pub struct MessageReader {
+    paths: IntoIter<PathBuf>,
+    lines: Option<Lines<BufReader<File>>>,
+}
+
+impl MessageReader {
+    fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            paths: paths.into_iter(),
+            lines: None,
+        }
+    }
+}
+
+impl Iterator for MessageReader {
+    type Item = Result<MessageData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(lines) = &mut self.lines {
+                match lines.next() {
+                    Some(Ok(line)) if line.is_empty() => continue,
+                    Some(Ok(line)) => return Some(serde_json::from_str(&line).map_err(Into::into)),
+                    Some(Err(why)) => return Some(Err(why.into())),
+                    None => self.lines = None,
+                }
+            }
+            let path = self.paths.next()?;
+            match File::open(&path) {
+                Ok(file) => self.lines = Some(BufReader::new(file).lines()),
+                Err(why) => return Some(Err(Error::file(&path)(why))),
+            }
+        }
+    }
+}
+
+fn write_line(writer: &mut impl Write, value: &impl Serialize) -> Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Writes through a temporary file so an interrupted write keeps the old file.
+fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let temporary = path.with_extension("json.tmp");
+    let mut writer = BufWriter::new(File::create(&temporary).map_err(Error::file(&temporary))?);
+    serde_json::to_writer(&mut writer, value)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(temporary, path).map_err(Error::file(path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    const GUILD: u64 = 111111111111111111;
+    const ALICE: u64 = 222222222222222222;
+    const CHANNEL: u64 = 666666666666666666;
+
+    /// Writes an archive of schema version 4, from before content was stored.
+    fn version_4_archive(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("{}-{}", name, process::id()));
+        fs::create_dir_all(dir.join(MESSAGES_DIR)).unwrap();
+        let mut metadata = serde_json::to_value(JsonData::new(
+            GuildId::new(GUILD),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        ))
+        .unwrap();
+        let root = metadata.as_object_mut().unwrap();
+        root.insert("version".to_string(), 4.into());
+        root.remove("fetched_until");
+        root.remove("forgotten_users");
+        write_json(&dir.join(METADATA_FILE), &metadata).unwrap();
+
+        let mut message = serde_json::to_value(MessageData::for_test(CHANNEL, 9, ALICE)).unwrap();
+        message.as_object_mut().unwrap().remove("content");
+        let shard = dir.join(MESSAGES_DIR).join(format!("{}.ndjson", CHANNEL));
+        fs::write(shard, format!("{}\n", message)).unwrap();
+        dir
+    }
+
+    #[test]
+    fn shards_are_upgraded_with_the_metadata() {
+        let dir = version_4_archive("sharded-upgrade");
+        let store = ShardedStore::open(&dir, GuildId::new(GUILD)).unwrap();
+        let data = store.load().unwrap();
+        assert_eq!(data.version, SCHEMA_VERSION);
+        assert_eq!(data.messages[&ChannelId::new(CHANNEL)].len(), 1);
+        assert_eq!(
+            data.fetched_until[&ChannelId::new(CHANNEL)],
+            MessageId::new(9)
+        );
+        assert!(!dir.join(UPGRADE_DIR).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_upgrades_are_finished() {
+        let dir = version_4_archive("sharded-interrupted");
+        let store = ShardedStore {
+            guild_id: GuildId::new(GUILD),
+            dir: dir.clone(),
+        };
+        // stop where a crash after writing meta.json would
+        let staging = dir.join(UPGRADE_DIR);
+        fs::create_dir_all(&staging).unwrap();
+        for shard in store.channel_paths().unwrap() {
+            let message = fs::read_to_string(&shard).unwrap();
+            let message =
+                migration::migrate_message(serde_json::from_str(message.trim()).unwrap(), 4)
+                    .unwrap();
+            fs::write(
+                staging.join(shard.file_name().unwrap()),
+                format!("{}\n", message),
+            )
+            .unwrap();
+        }
+        let metadata: Value =
+            serde_json::from_str(&fs::read_to_string(store.metadata_path()).unwrap()).unwrap();
+        write_json(
+            &store.metadata_path(),
+            &migration::migrate(metadata).unwrap(),
+        )
+        .unwrap();
+
+        let store = ShardedStore::open(&dir, GuildId::new(GUILD)).unwrap();
+        let messages = store
+            .messages()
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(!staging.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use itertools::Itertools;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+use serde::de::DeserializeOwned;
+use serenity::all::{ChannelId, ChannelType, EmojiId, GuildId, MessageId, UserId};
+
+use crate::error::{Error, Result};
+use crate::forget::ForgetMode;
+use crate::message_data::migration::USED_EMOJIS_RESET;
+use crate::message_data::{ChannelData, EmojiData, JsonData, MessageData, UserData};
+
+/// `MIGRATIONS[n]` upgrades a database from `user_version` `n` to `n + 1`.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE members (
+        user_id INTEGER PRIMARY KEY,
+        username TEXT NOT NULL,
+        display_name TEXT NOT NULL,
+        avatar_url TEXT,
+        is_bot INTEGER NOT NULL
+    );
+    CREATE TABLE channels (
+        channel_id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        channel_type INTEGER NOT NULL,
+        permission_overwrites TEXT NOT NULL,
+        parent_id INTEGER
+    );
+    CREATE TABLE emojis (
+        emoji_id INTEGER PRIMARY KEY,
+        alias TEXT NOT NULL,
+        image_url TEXT NOT NULL
+    );
+    CREATE TABLE messages (
+        message_id INTEGER PRIMARY KEY,
+        channel_id INTEGER NOT NULL,
+        author_id INTEGER NOT NULL,
+        mentions TEXT NOT NULL,
+        reactions TEXT NOT NULL,
+        reactors TEXT NOT NULL,
+        used_emojis TEXT NOT NULL,
+        send_time TEXT NOT NULL,
+        edit_time TEXT,
+        attachment_count INTEGER NOT NULL,
+        num_characters INTEGER NOT NULL,
+        is_pinned INTEGER NOT NULL
+    );
+    CREATE INDEX messages_channel ON messages (channel_id, message_id);
+    CREATE INDEX messages_author ON messages (author_id);
+",
+    "
+    ALTER TABLE messages ADD COLUMN reference TEXT;
+",
+    // used_emojis listed the reaction emojis, it now counts emojis in the content,
+    // which is only kept since migration 5, so `migrate` asks for a full crawl
+    "
+    UPDATE messages SET used_emojis = '{}';
+",
+    "
+    ALTER TABLE messages ADD COLUMN burst_reactions TEXT NOT NULL DEFAULT '{}';
+",
+    "
+    ALTER TABLE messages ADD COLUMN content TEXT;
+",
+    "
+    CREATE TABLE forgotten_users (
+        user_id INTEGER PRIMARY KEY,
+        mode TEXT NOT NULL
+    );
+",
+    // the getter used to stop at the newest stored message
+    "
+    CREATE TABLE fetched_until (
+        channel_id INTEGER PRIMARY KEY,
+        message_id INTEGER NOT NULL
+    );
+    INSERT INTO fetched_until SELECT channel_id, MAX(message_id) FROM messages GROUP BY channel_id;
+",
+    "
+    ALTER TABLE messages ADD COLUMN burst_reactors TEXT NOT NULL DEFAULT '{}';
+",
+];
+
+const MESSAGE_COLUMNS: &str = "message_id, channel_id, author_id, mentions, reactions, reactors, \
+    used_emojis, send_time, edit_time, attachment_count, num_characters, is_pinned, reference, \
+    burst_reactions, content, burst_reactors";
+
+pub struct SqliteStore {
+    guild_id: GuildId,
+    connection: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>, guild_id: GuildId) -> Result<Self> {
+        let mut connection = Connection::open(path)?;
+        migrate(&mut connection)?;
+        Ok(Self {
+            guild_id,
+            connection,
+        })
+    }
+
+    pub fn load(&self) -> Result<JsonData> {
+        let mut data = self.load_metadata()?;
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {} FROM messages ORDER BY channel_id, message_id DESC",
+            MESSAGE_COLUMNS
+        ))?;
+        for message in statement.query_map([], message_from_row)? {
+            let message = message?;
+            data.messages
+                .entry(message.channel_id)
+                .or_default()
+                .push(message);
+        }
+        Ok(data)
+    }
+
+    pub fn for_each_message(&self, mut f: impl FnMut(MessageData)) -> Result<()> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("SELECT {} FROM messages", MESSAGE_COLUMNS))?;
+        for message in statement.query_map([], message_from_row)? {
+            f(message?);
+        }
+        Ok(())
+    }
+
+    pub fn load_metadata(&self) -> Result<JsonData> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT user_id, username, display_name, avatar_url, is_bot FROM members")?;
+        let members = statement
+            .query_map([], |row| {
+                let user = UserData::new(
+                    UserId::new(row.get(0)?),
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                );
+                Ok((user.user_id, user))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut statement = self.connection.prepare(
+            "SELECT channel_id, name, channel_type, permission_overwrites, parent_id FROM channels",
+        )?;
+        let channels = statement
+            .query_map([], |row| {
+                let channel = ChannelData::new(
+                    ChannelId::new(row.get(0)?),
+                    row.get(1)?,
+                    ChannelType::from(row.get::<_, u8>(2)?),
+                    json_column(row, 3)?,
+                    row.get::<_, Option<u64>>(4)?.map(ChannelId::new),
+                );
+                Ok((channel.channel_id, channel))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT emoji_id, alias, image_url FROM emojis")?;
+        let emojis = statement
+            .query_map([], |row| {
+                let emoji_id = EmojiId::new(row.get(0)?);
+                let emoji = EmojiData {
+                    emoji_id,
+                    alias: row.get(1)?,
+                    image_url: row.get(2)?,
+                };
+                Ok((emoji_id, emoji))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT channel_id, message_id FROM fetched_until")?;
+        let fetched_until = statement
+            .query_map([], |row| {
+                Ok((ChannelId::new(row.get(0)?), MessageId::new(row.get(1)?)))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut data = JsonData::new(self.guild_id, members, channels, emojis, HashMap::new());
+        data.fetched_until = fetched_until;
+        data.forgotten_users = self.forgotten_users()?;
+        Ok(data)
+    }
+
+    pub fn forgotten_users(&self) -> Result<HashMap<UserId, ForgetMode>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT user_id, mode FROM forgotten_users")?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter()
+            .map(|(user_id, mode)| Ok((UserId::new(user_id), mode.parse()?)))
+            .collect()
+    }
+
+    pub fn save(&mut self, data: &JsonData) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        write_metadata(&transaction, data)?;
+        transaction.execute("DELETE FROM messages", [])?;
+        for message in data.messages.values().flatten() {
+            insert_message(&transaction, message)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn save_metadata(&mut self, data: &JsonData) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        write_metadata(&transaction, data)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn get_message(&self, message_id: MessageId) -> Result<Option<MessageData>> {
+        let message = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT {} FROM messages WHERE message_id = ?1",
+                    MESSAGE_COLUMNS
+                ),
+                [message_id.get()],
+                message_from_row,
+            )
+            .optional()?;
+        Ok(message)
+    }
+
+    pub fn update_message(
+        &mut self,
+        message_id: MessageId,
+        f: impl FnOnce(&mut MessageData),
+    ) -> Result<bool> {
+        let mut message = match self.get_message(message_id)? {
+            Some(message) => message,
+            None => return Ok(false),
+        };
+        f(&mut message);
+        self.upsert_message(&message)?;
+        Ok(true)
+    }
+
+    pub fn upsert_message(&mut self, message: &MessageData) -> Result<()> {
+        write_message(&self.connection, message, "INSERT OR REPLACE")
+    }
+
+    pub fn delete_message(&mut self, message_id: MessageId) -> Result<bool> {
+        let deleted = self.connection.execute(
+            "DELETE FROM messages WHERE message_id = ?1",
+            [message_id.get()],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// Replaces the metadata and adds messages that are not stored yet.
+    pub fn merge(&mut self, data: &JsonData) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        write_metadata(&transaction, data)?;
+        for message in data.messages.values().flatten() {
+            insert_message(&transaction, message)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(Error::NewerSchema {
+            found: version,
+            supported: MIGRATIONS.len(),
+        });
+    }
+    // a new database has no messages to count again
+    if (1..3).contains(&version) {
+        eprintln!("{}", USED_EMOJIS_RESET);
+    }
+    let transaction = connection.transaction()?;
+    for migration in &MIGRATIONS[version..] {
+        transaction.execute_batch(migration)?;
+    }
+    transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    transaction.commit()?;
+    Ok(())
+}
+
+fn write_metadata(transaction: &Transaction, data: &JsonData) -> Result<()> {
+    transaction.execute_batch(
+        "DELETE FROM members; DELETE FROM channels; DELETE FROM emojis; DELETE FROM fetched_until;
+         DELETE FROM forgotten_users;",
+    )?;
+
+    let mut statement = transaction.prepare(
+        "INSERT INTO members (user_id, username, display_name, avatar_url, is_bot)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for user in data.members.values() {
+        statement.execute(params![
+            user.user_id.get(),
+            user.username,
+            user.display_name,
+            user.avatar_url,
+            user.is_bot,
+        ])?;
+    }
+
+    let mut statement = transaction.prepare(
+        "INSERT INTO channels (channel_id, name, channel_type, permission_overwrites, parent_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for channel in data.channels.values() {
+        statement.execute(params![
+            channel.channel_id.get(),
+            channel.name,
+            u8::from(channel.channel_type),
+            serde_json::to_string(&channel.permission_overwrites)?,
+            channel.parent_id.map(|parent_id| parent_id.get()),
+        ])?;
+    }
+
+    let mut statement = transaction
+        .prepare("INSERT INTO emojis (emoji_id, alias, image_url) VALUES (?1, ?2, ?3)")?;
+    for emoji in data.emojis.values() {
+        statement.execute(params![emoji.emoji_id.get(), emoji.alias, emoji.image_url])?;
+    }
+
+    let mut statement = transaction
+        .prepare("INSERT INTO fetched_until (channel_id, message_id) VALUES (?1, ?2)")?;
+    for (channel_id, message_id) in &data.fetched_until {
+        statement.execute(params![channel_id.get(), message_id.get()])?;
+    }
+
+    let mut statement =
+        transaction.prepare("INSERT INTO forgotten_users (user_id, mode) VALUES (?1, ?2)")?;
+    for (user_id, mode) in &data.forgotten_users {
+        statement.execute(params![user_id.get(), mode.to_string()])?;
+    }
+    Ok(())
+}
+
+fn insert_message(connection: &Connection, message: &MessageData) -> Result<()> {
+    write_message(connection, message, "INSERT OR IGNORE")
+}
+
+fn write_message(connection: &Connection, message: &MessageData, insert: &str) -> Result<()> {
+    let placeholders = (1..=MESSAGE_COLUMNS.split(',').count())
+        .map(|i| format!("?{}", i))
+        .join(", ");
+    let mut statement = connection.prepare_cached(&format!(
+        "{} INTO messages ({}) VALUES ({})",
+        insert, MESSAGE_COLUMNS, placeholders
+    ))?;
+    statement.execute(params![
+        message.message_id.get(),
+        message.channel_id.get(),
+        message.author_id.get(),
+        serde_json::to_string(&message.mentions)?,
+        serde_json::to_string(&message.reactions)?,
+        serde_json::to_string(&message.reactors)?,
+        serde_json::to_string(&message.used_emojis)?,
+        message.send_time,
+        message.edit_time,
+        message.attachment_count,
+        message.num_characters,
+        message.is_pinned,
+        message
+            .reference
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?,
+        serde_json::to_string(&message.burst_reactions)?,
+        message.content,
+        serde_json::to_string(&message.burst_reactors)?,
+    ])?;
+    Ok(())
+}
+
+fn message_from_row(row: &Row) -> rusqlite::Result<MessageData> {
+    Ok(MessageData {
+        message_id: MessageId::new(row.get(0)?),
+        channel_id: ChannelId::new(row.get(1)?),
+        author_id: UserId::new(row.get(2)?),
+        mentions: json_column(row, 3)?,
+        reactions: json_column(row, 4)?,
+        reactors: json_column(row, 5)?,
+        used_emojis: json_column(row, 6)?,
+        send_time: row.get(7)?,
+        edit_time: row.get(8)?,
+        attachment_count: row.get(9)?,
+        num_characters: row.get(10)?,
+        is_pinned: row.get(11)?,
+        reference: json_column(row, 12)?,
+        burst_reactions: json_column(row, 13)?,
+        content: row.get(14)?,
+        burst_reactors: json_column(row, 15)?,
+    })
+}
+
+/// Reads JSON text, where NULL reads as JSON `null` for optional fields.
+fn json_column<T: DeserializeOwned>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    let text = row
+        .get::<_, Option<String>>(index)?
+        .unwrap_or_else(|| "null".to_string());
+    serde_json::from_str(&text)
+        .map_err(|why| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(why)))
+}
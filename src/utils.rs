@@ -1,21 +1,45 @@
-use serenity::all::{GuildId, Http, Message, ReactionType, Result, User};
+use std::collections::{HashMap, HashSet};
+use std::env::{self, VarError};
 
-#[allow(dead_code)]
+use serenity::all::{
+    ChannelId, GuildChannel, GuildId, Http, Message, PermissionOverwriteType, ReactionType, Result,
+    User, UserId,
+};
+use serenity::http::{LightMethod, Request, Route};
+
+use crate::error::Error;
+use crate::message_data::Emoji;
+
+/// Fetches every user of a reaction, of its super reactions when `burst` is set.
 pub async fn get_reactions(
     http: impl AsRef<Http>,
     message: &Message,
     reaction_type: ReactionType,
+    burst: bool,
 ) -> Result<Vec<User>> {
     let mut users = Vec::<User>::new();
-    let mut last_user_id: Option<_> = None;
+    let mut last_user_id: Option<UserId> = None;
     let limit = 100;
+    let reaction = reaction_type.as_data();
     loop {
-        let new_users = message
-            .reaction_users(&http, reaction_type.clone(), Some(limit), last_user_id)
-            .await?;
+        // serenity's reaction_users has no way to ask for the super reactions
+        let mut params = vec![
+            ("limit", limit.to_string()),
+            ("type", u8::from(burst).to_string()),
+        ];
+        if let Some(last_user_id) = last_user_id {
+            params.push(("after", last_user_id.to_string()));
+        }
+        let route = Route::ChannelMessageReactionEmoji {
+            channel_id: message.channel_id,
+            message_id: message.id,
+            reaction: &reaction,
+        };
+        let request = Request::new(route, LightMethod::Get).params(Some(params));
+        let new_users: Vec<User> = http.as_ref().fire(request).await?;
         let length = new_users.len();
         users.extend(new_users);
-        if (length as u8) < limit {
+        if length < limit {
             break;
         }
         last_user_id = Some(users.last().unwrap().id);
@@ -23,7 +47,137 @@ pub async fn get_reactions(
     Ok(users)
 }
 
+/// Fetches the users of the normal and of the super reactions of a message.
+/// A reaction that cannot be fetched is logged and left out.
+pub async fn get_reactors(
+    http: impl AsRef<Http>,
+    message: &Message,
+) -> (HashMap<Emoji, Vec<UserId>>, HashMap<Emoji, Vec<UserId>>) {
+    let mut reactors = HashMap::<Emoji, Vec<UserId>>::new();
+    let mut burst_reactors = HashMap::<Emoji, Vec<UserId>>::new();
+    for reaction in &message.reactions {
+        let emoji = Emoji::from_reaction(reaction.reaction_type.clone());
+        let kinds = [
+            (false, reaction.count_details.normal, &mut reactors),
+            (true, reaction.count_details.burst, &mut burst_reactors),
+        ];
+        for (burst, count, reactors) in kinds {
+            if count == 0 {
+                continue;
+            }
+            match get_reactions(&http, message, reaction.reaction_type.clone(), burst).await {
+                Ok(users) => {
+                    reactors.insert(
+                        emoji.clone(),
+                        users.into_iter().map(|user| user.id).collect(),
+                    );
+                }
+                Err(why) => eprintln!(
+                    "Failed to fetch the users of {:?} on message {}: {:?}",
+                    emoji, message.id, why
+                ),
+            }
+        }
+    }
+    (reactors, burst_reactors)
+}
+
+fn is_private_archive_channel(channel: &GuildChannel, guild_id: GuildId) -> bool {
+    for permission_overwrite in channel.permission_overwrites.iter() {
+        match permission_overwrite.kind {
+            PermissionOverwriteType::Role(role_id) => {
+                if role_id.get() != guild_id.get() {
+                    continue;
+                }
+            }
+            _ => continue,
+        }
+        return permission_overwrite.deny.view_channel();
+    }
+    false
+}
+
+/// Whether the getter leaves a channel out, and the recorder with it: channels
+/// hidden from everyone, and the threads created in them.
+pub fn is_skipped_channel(
+    channel: &GuildChannel,
+    channels: &HashMap<ChannelId, GuildChannel>,
+    guild_id: GuildId,
+) -> bool {
+    if is_private_archive_channel(channel, guild_id) {
+        return true;
+    }
+    // threads inherit the permissions of the channel they were created in
+    channel.thread_metadata.is_some()
+        && channel
+            .parent_id
+            .and_then(|parent_id| channels.get(&parent_id))
+            .is_some_and(|parent| is_private_archive_channel(parent, guild_id))
+}
+
+/// Loads `.env` into the environment. A missing file is fine, as the variables
+/// may be set otherwise.
+pub fn load_env() -> crate::error::Result<()> {
+    match dotenvy::dotenv() {
+        Ok(_) => Ok(()),
+        Err(why) if why.not_found() => Ok(()),
+        Err(why) => Err(why.into()),
+    }
+}
+
+pub fn env_var(name: &'static str) -> crate::error::Result<String> {
+    env::var(name).map_err(|why| match why {
+        VarError::NotPresent => Error::MissingEnv(name),
+        VarError::NotUnicode(value) => Error::InvalidEnv {
+            name,
+            value: value.to_string_lossy().into_owned(),
+        },
+    })
+}
+
+/// Reads the guild to work on from `GUILD_ID`.
+pub fn guild_id_from_env() -> crate::error::Result<GuildId> {
+    let value = env_var("GUILD_ID")?;
+    value.parse().map_err(|_| Error::InvalidEnv {
+        name: "GUILD_ID",
+        value,
+    })
+}
+
+/// Channels whose message content is stored, from the comma separated
+/// `CONTENT_CHANNELS` environment variable, which also covers their threads but
+/// not the channels of a listed category. Content is not stored by default.
+pub fn content_channels() -> crate::error::Result<HashSet<ChannelId>> {
+    env::var("CONTENT_CHANNELS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse().map_err(|_| Error::InvalidEnv {
+                name: "CONTENT_CHANNELS",
+                value: id.to_string(),
+            })
+        })
+        .collect()
+}
+
 #[inline]
 pub fn filename(guild_id: GuildId) -> String {
     format!("outputs/{}.json", guild_id)
 }
+
+#[inline]
+pub fn sqlite_filename(guild_id: GuildId) -> String {
+    format!("outputs/{}.sqlite3", guild_id)
+}
+
+#[inline]
+pub fn shard_dirname(guild_id: GuildId) -> String {
+    format!("outputs/{}", guild_id)
+}
+
+#[inline]
+pub fn checkpoint_filename(guild_id: GuildId) -> String {
+    format!("outputs/{}.checkpoint.json", guild_id)
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// English words too common to say anything about a user or channel.
+const STOPWORDS: &[&str] = &[
+    "a", "about", "all", "also", "am", "an", "and", "any", "are", "as", "at", "be", "been", "but",
+    "by", "can", "could", "did", "do", "does", "for", "from", "had", "has", "have", "he", "her",
+    "him", "his", "how", "i", "if", "in", "into", "is", "it", "its", "just", "like", "me", "my",
+    "no", "not", "of", "on", "one", "or", "our", "out", "so", "some", "that", "the", "their",
+    "them", "then", "there", "they", "this", "to", "too", "up", "us", "was", "we", "were", "what",
+    "when", "which", "who", "will", "with", "would", "you", "your",
+];
+
+/// Japanese bigrams that are mostly grammar. Bigrams of hiragana only are
+/// already dropped, so these are the ones mixing in kanji or katakana.
+const CJK_STOPWORDS: &[&str] = &[
+    "思う", "思っ", "言う", "言っ", "見て", "出来", "本当", "自分",
+];
+
+pub type WordCounter = HashMap<String, usize>;
+
+/// Splits content into words for counting.
+///
+/// Latin words are lowercased and kept whole. Japanese and Chinese text has no
+/// spaces, so runs of kanji and hiragana become character bigrams, a lone
+/// kanji is kept as is, and katakana runs, usually loanwords, are kept whole. URLs, mentions, custom
+/// emojis, stopwords and bigrams of hiragana only are left out.
+pub fn tokenize(content: &str) -> Vec<String> {
+    static MARKUP: OnceLock<Regex> = OnceLock::new();
+    let markup = MARKUP.get_or_init(|| {
+        Regex::new(r"https?://\S+|<a?:\w+:\d+>|<[@#][!&]?\d+>|```[\s\S]*?```|`[^`]*`").unwrap()
+    });
+    let text = markup.replace_all(content, " ");
+
+    let mut tokens = Vec::<String>::new();
+    let mut run = String::new();
+    let mut run_script = Script::Other;
+    for c in text.chars().chain([' ']) {
+        let script = Script::of(c);
+        if script != run_script && !run.is_empty() {
+            push_run(&mut tokens, &run, run_script);
+            run.clear();
+        }
+        run_script = script;
+        if script != Script::Other {
+            run.push(c);
+        }
+    }
+    tokens
+}
+
+/// Adds the tokens of `content` to `counter`.
+pub fn count_words(content: &str, counter: &mut WordCounter) {
+    for token in tokenize(content) {
+        *counter.entry(token).or_default() += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    /// Kanji and hiragana, which are written mixed in a sentence.
+    Ideographic,
+    Katakana,
+    Other,
+}
+
+impl Script {
+    fn of(c: char) -> Self {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '\'' => Self::Latin,
+            '\u{3041}'..='\u{309F}' | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '々' => {
+                Self::Ideographic
+            }
+            '\u{30A1}'..='\u{30FF}' | '\u{FF66}'..='\u{FF9F}' => Self::Katakana,
+            c if c.is_alphanumeric() => Self::Latin,
+            _ => Self::Other,
+        }
+    }
+}
+
+fn push_run(tokens: &mut Vec<String>, run: &str, script: Script) {
+    match script {
+        Script::Latin => {
+            let word = run.trim_matches('\'').to_lowercase();
+            let is_number = word.chars().all(|c| c.is_ascii_digit());
+            if word.chars().count() >= 2 && !is_number && !STOPWORDS.contains(&word.as_str()) {
+                tokens.push(word);
+            }
+        }
+        Script::Katakana => {
+            // a lone ー or ッ is not a word
+            if run.chars().count() >= 2 {
+                tokens.push(run.to_string());
+            }
+        }
+        Script::Ideographic => {
+            let chars: Vec<char> = run.chars().collect();
+            // a run of one character has no bigram, but a lone kanji is a word
+            if let [c] = chars[..] {
+                if !is_hiragana(c) {
+                    tokens.push(c.to_string());
+                }
+            }
+            for pair in chars.windows(2) {
+                if pair.iter().all(|c| is_hiragana(*c)) {
+                    continue;
+                }
+                let bigram: String = pair.iter().collect();
+                if !CJK_STOPWORDS.contains(&bigram.as_str()) {
+                    tokens.push(bigram);
+                }
+            }
+        }
+        Script::Other => {}
+    }
+}
+
+fn is_hiragana(c: char) -> bool {
+    matches!(c, '\u{3041}'..='\u{309F}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latin_words_are_lowercased_without_stopwords() {
+        assert_eq!(
+            tokenize("The Rust book, isn't it 2024?"),
+            ["rust", "book", "isn't"]
+        );
+    }
+
+    #[test]
+    fn markup_is_left_out() {
+        let content = "see https://example.com <@123> <:blob:456> `code` here";
+        assert_eq!(tokenize(content), ["see", "here"]);
+    }
+
+    #[test]
+    fn japanese_becomes_bigrams_and_katakana_words() {
+        assert_eq!(
+            tokenize("東京タワーに行く"),
+            ["東京", "タワー", "に行", "行く"]
+        );
+        assert_eq!(tokenize("コーヒーを飲む"), ["コーヒー", "を飲", "飲む"]);
+    }
+
+    #[test]
+    fn a_lone_kanji_is_a_word() {
+        assert_eq!(tokenize("猫 が 好き"), ["猫", "好き"]);
+    }
+}